@@ -0,0 +1,67 @@
+use std::{future::Future, pin::Pin, sync::Arc};
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use webserver::{ep_wrap, app::{HttpResult, MethodSet, Pattern, Router}, http::{HttpRequest, HttpResponse, Method}};
+
+async fn handler(_req: &mut HttpRequest<'_>, _captures: Vec<&'_ str>) -> HttpResult {
+    Ok(HttpResponse::create_200_ok())
+}
+
+/// A route table shaped like a real service: many resources, each with a few nested routes.
+fn patterns(resources: usize) -> Vec<String> {
+    let mut pats = Vec::new();
+    for i in 0..resources {
+        pats.push(format!("/api/v1/resource{}", i));
+        pats.push(format!("/api/v1/resource{}/{{}}", i));
+        pats.push(format!("/api/v1/resource{}/{{}}/items/{{}}", i));
+        pats.push(format!("/static/resource{}/{{path}}", i));
+    }
+    pats
+}
+
+fn bench_routing(c: &mut Criterion) {
+    let mut group = c.benchmark_group("routing");
+    for resources in [10, 100, 250] {
+        let pats = patterns(resources);
+        let linear: Vec<Pattern> = pats.iter().map(|p| Pattern::from_str(p).unwrap()).collect();
+        let mut router = Router::new();
+        let mut methods = MethodSet::new();
+        methods.insert(Method::GET);
+        for p in &pats {
+            router.register(p, ep_wrap!(handler), methods.clone()).unwrap();
+        }
+        // The last resource is the worst case for a linear scan.
+        let url = format!("/api/v1/resource{}/42/items/7", resources - 1);
+
+        group.bench_with_input(BenchmarkId::new("linear", pats.len()), &url, |b, url| {
+            b.iter(|| linear.iter().find_map(|p| p.match_url(black_box(url))))
+        });
+        group.bench_with_input(BenchmarkId::new("tree", pats.len()), &url, |b, url| {
+            b.iter(|| router.find(black_box(url)).map(|(_, captures)| captures))
+        });
+    }
+    group.finish();
+}
+
+/// Patterns with several variable-width placeholders against a long url,
+/// which must not make matching cost more than linear in the url.
+fn bench_adversarial(c: &mut Criterion) {
+    let mut group = c.benchmark_group("adversarial");
+    let pats = ["/{path}/x/{path}/y/{path}", "/{path}/{path}/{path}", "/{}/{all}"];
+    let mut router = Router::new();
+    let mut methods = MethodSet::new();
+    methods.insert(Method::GET);
+    for p in pats {
+        router.register(p, ep_wrap!(handler), methods.clone()).unwrap();
+    }
+    for len in [1200, 8000] {
+        let url = format!("/{}", "a/x/b/y/".repeat(len / 8));
+        group.bench_with_input(BenchmarkId::new("tree", url.len()), &url, |b, url| {
+            b.iter(|| router.find(black_box(url)).map(|(_, captures)| captures))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_routing, bench_adversarial);
+criterion_main!(benches);
@@ -1,13 +1,147 @@
 use std::{sync::{atomic::AtomicU64, Arc}, time::Duration};
-use tokio::{io::{self, BufReader}, net:: TcpListener, spawn, sync::Mutex, task::yield_now, time::Instant};
-use crate::{http::Method, app::{EndPoint, MethodSet, Processor}};
+use futures::future::select_all;
+use tokio::{io::{self, AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader}, net::{TcpListener, TcpStream}, select, spawn, sync::{mpsc, Semaphore}, task::JoinSet, time::{sleep, timeout}};
+use crate::{http::{HttpResponse, Method, RequestLimits}, app::{limit::ConnPermit, shutdown::wait_for_signal, AtCapacity, ConnectionLimit, EndPoint, ErrorMapper, MethodSet, Middleware, PathNormalization, Processor, RouteTable, RouteTableEndPoint, Router, ShutdownHandle, StaticDir}};
 
-use super::processor;
+/// Counts a connection for as long as its task lives, so that the count stays
+/// right even when the task unwinds.
+struct ConnGuard(Arc<AtomicU64>);
+
+impl ConnGuard {
+    fn new(count: Arc<AtomicU64>) -> Self {
+        count.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        ConnGuard(count)
+    }
+}
+
+impl Drop for ConnGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, std::sync::atomic::Ordering::Relaxed);
+    }
+}
+
+/// How connections are let in and how long they may sit idle.
+#[derive(Debug, Clone)]
+struct Admission {
+    limit: ConnectionLimit,
+    at_capacity: AtCapacity,
+    keep_alive: Duration,
+    /// Connections being turned away with a 503, see [`MAX_REJECTING`].
+    rejecting: Arc<Semaphore>,
+}
+
+/// How long a connection turned away at capacity is read from after its 503.
+const REJECT_LINGER: Duration = Duration::from_millis(500);
+
+/// Most connections answered with a 503 at once; beyond that they are closed
+/// without an answer, so that an overload does not pile up lingering sockets.
+const MAX_REJECTING: usize = 64;
+
+/// Accepts connections from every listener until shutdown. Waiting ones wait
+/// for a free slot before they are accepted, so that they queue up in the
+/// listen backlog rather than in tasks of their own.
+async fn accept_connections(listeners: Vec<TcpListener>, admission: Admission, stream_tx: mpsc::Sender<(TcpStream, Option<ConnPermit>)>, shutdown: ShutdownHandle) {
+    if listeners.is_empty() {
+        return;
+    }
+    loop {
+        let permit = match admission.at_capacity {
+            AtCapacity::Wait => select! {
+                permit = admission.limit.acquire() => Some(permit),
+                _ = shutdown.wait() => break,
+            },
+            AtCapacity::Reject => None,
+        };
+        select! {
+            (accepted, _, _) = select_all(listeners.iter().map(|l| Box::pin(l.accept()))) => if let Ok((stream, _)) = accepted {
+                if stream_tx.send((stream, permit)).await.is_err() {
+                    break;
+                }
+            },
+            _ = shutdown.wait() => break,
+        }
+    }
+}
+
+/// Answers a connection turned away at capacity with a 503. The request is
+/// read off before closing, as closing with unread input resets the
+/// connection and the client may lose the answer.
+async fn reject_connection(mut stream: TcpStream) {
+    let mut resp = HttpResponse::create_503_service_unavailable();
+    resp.headers.insert("Connection", "close");
+    if let Err(e) = resp.write_to(&mut stream).await {
+        println!("Connection Error: {:?}", e);
+        return;
+    }
+    if stream.shutdown().await.is_err() {
+        return;
+    }
+    let mut sink = [0_u8; 1024];
+    let _ = timeout(REJECT_LINGER, async {
+        while matches!(stream.read(&mut sink).await, Ok(n) if n > 0) {}
+    }).await;
+}
+
+/// Serves the requests of one connection once admitted, until it closes,
+/// sits idle for longer than the keep-alive timeout, or the application shuts
+/// down: an idle connection is closed right away, a busy one once its
+/// response is out. A connection accepted without a permit gets one now or
+/// is turned away.
+async fn serve_connection(processor: Arc<Processor>, conns_count: Arc<AtomicU64>, admission: Admission, stream: TcpStream, permit: Option<ConnPermit>) {
+    let _guard = ConnGuard::new(conns_count);
+    let _permit = match permit.or_else(|| admission.limit.try_acquire()) {
+        Some(permit) => permit,
+        None => {
+            if let Ok(_rejecting) = admission.rejecting.try_acquire() {
+                reject_connection(stream).await;
+            }
+            return;
+        },
+    };
+    println!("Connection Create");
+    let (rx, mut tx) = stream.into_split();
+    let mut buf_rx = BufReader::new(rx);
+    loop {
+        let closed = select! {
+            read = buf_rx.fill_buf() => !matches!(read, Ok(buf) if !buf.is_empty()),
+            _ = sleep(admission.keep_alive) => true,
+            _ = processor.shutdown.wait() => true,
+        };
+        if closed {
+            break;
+        }
+        match processor.handle(&mut buf_rx, &mut tx).await {
+            Ok(resp) => {
+                match resp.connect_state {
+                    super::ConnectionState::Opening => {},
+                    super::ConnectionState::Closed => break,
+                }
+            },
+            Err(e) => {
+                println!("Connection Error: {:?}", e);
+                break;
+            },
+        }
+    }
+    println!("Connection End");
+}
 
 pub struct Application {
     pub listeners: Vec<TcpListener>,
     pub processor: Processor,
+    /// Open connections, including the ones being turned away at capacity.
     pub conns_count: AtomicU64,
+    conn_limit: ConnectionLimit,
+    at_capacity: AtCapacity,
+    keep_alive: Duration,
+    drain_timeout: Duration,
+    handle_signals: bool,
+}
+
+impl Default for Application {
+    fn default() -> Self {
+        Application::new()
+    }
 }
 
 impl Application {
@@ -17,50 +151,112 @@ impl Application {
             listeners: Vec::new(),
             processor: Processor::new(),
             conns_count: AtomicU64::new(0),
+            conn_limit: ConnectionLimit::new(1024),
+            at_capacity: AtCapacity::Wait,
+            keep_alive: Duration::from_secs(5),
+            drain_timeout: Duration::from_secs(30),
+            handle_signals: true,
         }
     }
 
+    /// Serves until shut down through [`Self::shutdown_handle`] or, unless
+    /// disabled, by SIGINT or SIGTERM; then stops accepting, waits up to the
+    /// drain timeout for open connections to finish and closes the rest.
     pub async fn run(self) {
+        for conflict in self.processor.router.conflicts() {
+            println!("Route Conflict: {}", conflict);
+        }
+        let shutdown = self.processor.shutdown.clone();
+        let signals = self.handle_signals.then(|| {
+            let shutdown = shutdown.clone();
+            spawn(async move {
+                wait_for_signal().await;
+                println!("Shutdown Signal");
+                shutdown.shutdown();
+            })
+        });
         let processor = Arc::new(self.processor);
         let conns_count = Arc::new(self.conns_count);
-        for listener in self.listeners {
-            let loc_processor = processor.clone();
-            let conns_count = conns_count.clone();
-            spawn(async move {
-                loop {
-                    if let Ok((stream, _)) = listener.accept().await {
-                        let loc_processor = loc_processor.clone();
-                        let conns_count = conns_count.clone();
-                        spawn(async move {
-                            println!("Connection Create");
-                            conns_count.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
-                            let (rx, mut tx) = stream.into_split();
-                            let mut buf_rx = BufReader::new(rx);
-                            let start_instant = Instant::now();
-                            while conns_count.load(std::sync::atomic::Ordering::Relaxed) < 20
-                                    // very bad
-                                    && start_instant + Duration::from_secs(5) >= Instant::now() {
-                                match loc_processor.handle(&mut buf_rx, &mut tx).await {
-                                    Ok(resp) => {
-                                        match resp.connect_state {
-                                            super::ConnectionState::Opening => {},
-                                            super::ConnectionState::Closed => break,
-                                        }
-                                    },
-                                    Err(e) => println!("Handle Error: {:?}", e),
-                                }
-                            }
-                            println!("Connection End");
-                            conns_count.fetch_sub(1, std::sync::atomic::Ordering::Relaxed);
-                        });
+        let admission = Admission {
+            limit: self.conn_limit,
+            at_capacity: self.at_capacity,
+            keep_alive: self.keep_alive,
+            rejecting: Arc::new(Semaphore::new(MAX_REJECTING)),
+        };
+
+        let (stream_tx, mut stream_rx) = mpsc::channel(64);
+        let accepts = spawn(accept_connections(self.listeners, admission.clone(), stream_tx, shutdown.clone()));
+
+        let mut conns = JoinSet::new();
+        loop {
+            select! {
+                Some((stream, permit)) = stream_rx.recv() => {
+                    conns.spawn(serve_connection(processor.clone(), conns_count.clone(), admission.clone(), stream, permit));
+                },
+                Some(res) = conns.join_next(), if !conns.is_empty() => {
+                    if let Err(e) = res {
+                        println!("Connection Error: {:?}", e);
                     }
-                }
-            });
+                },
+                _ = shutdown.wait() => break,
+            }
         }
-        // connection_pool.run(processor, connection_sender).await;
-        loop {
-            yield_now().await;
+
+        println!("Shutting Down");
+        // Closing the channel first frees the accept task blocked on a full one.
+        drop(stream_rx);
+        if let Err(e) = accepts.await {
+            println!("Accept Error: {:?}", e);
+        }
+        if timeout(self.drain_timeout, async { while conns.join_next().await.is_some() {} }).await.is_err() {
+            println!("Drain Timeout: closing {} connections", conns.len());
+            conns.shutdown().await;
         }
+        if let Some(signals) = signals {
+            signals.abort();
+        }
+        println!("Server Stopped");
+    }
+
+    /// Stops [`Self::run`] from elsewhere, e.g. another task or a test.
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.processor.shutdown.clone()
+    }
+
+    /// Serves at most `max` connections at once, 1024 by default. The limit
+    /// can be changed while running through [`Self::connection_limit`].
+    pub fn max_connections(self, max: usize) -> Self {
+        self.conn_limit.set_limit(max);
+        self
+    }
+
+    /// The limit on connections served at once, to adjust at runtime.
+    pub fn connection_limit(&self) -> ConnectionLimit {
+        self.conn_limit.clone()
+    }
+
+    /// What new connections get at the connection limit; they wait by default.
+    pub fn at_capacity(mut self, at_capacity: AtCapacity) -> Self {
+        self.at_capacity = at_capacity;
+        self
+    }
+
+    /// How long a connection may wait for its next request; 5 seconds by default.
+    pub fn keep_alive_timeout(mut self, timeout: Duration) -> Self {
+        self.keep_alive = timeout;
+        self
+    }
+
+    /// How long shutdown waits for open connections before closing them; 30 seconds by default.
+    pub fn drain_timeout(mut self, timeout: Duration) -> Self {
+        self.drain_timeout = timeout;
+        self
+    }
+
+    /// Whether SIGINT and SIGTERM shut the application down; on by default.
+    pub fn handle_signals(mut self, on: bool) -> Self {
+        self.handle_signals = on;
+        self
     }
 
     pub async fn listen_tcp(mut self, addr:&str) -> io::Result<Self>{
@@ -68,11 +264,99 @@ impl Application {
         Ok(self)
     }
 
-    pub fn register<'a>(mut self, pat: &'a str, handle: Arc<dyn EndPoint>, methods: MethodSet) -> Self{
-        self.processor.router.register(pat, handle, methods);
+    /// Turns route conflicts into registration errors, so that they stop the
+    /// application at startup; otherwise they are only reported by `run`.
+    /// Panics if routes registered before already conflict.
+    pub fn strict_routes(mut self) -> Self {
+        if let Err(e) = self.processor.router.set_strict(true) {
+            panic!("cannot make routes strict: {}", e);
+        }
+        self
+    }
+
+    /// Registers a route; panics if `pat` is not a valid pattern, or in
+    /// strict mode if it conflicts with an earlier route.
+    pub fn register(self, pat: &str, handle: Arc<dyn EndPoint>, methods: MethodSet) -> Self{
+        self.register_with(pat, handle, methods, Vec::new())
+    }
+
+    pub fn register_with(mut self, pat: &str, handle: Arc<dyn EndPoint>, methods: MethodSet, middlewares: Vec<Arc<dyn Middleware>>) -> Self{
+        if let Err(e) = self.processor.router.register_with(pat, handle, methods, middlewares) {
+            panic!("cannot register route {:?}: {}", pat, e);
+        }
+        self
+    }
+
+    /// Registers a route under `name`, see [`Router::url_for`]; panics like [`Self::register`].
+    pub fn register_named(mut self, name: &str, pat: &str, handle: Arc<dyn EndPoint>, methods: MethodSet, middlewares: Vec<Arc<dyn Middleware>>) -> Self{
+        if let Err(e) = self.processor.router.register_named(name, pat, handle, methods, middlewares) {
+            panic!("cannot register route {:?}: {}", pat, e);
+        }
+        self
+    }
+
+    /// Sets how request paths are cleaned up before routing.
+    pub fn normalization(mut self, normalization: PathNormalization) -> Self {
+        self.processor.normalization = normalization;
+        self
+    }
+
+    /// Sets how large requests may be, see [`RequestLimits`].
+    pub fn limits(mut self, limits: RequestLimits) -> Self {
+        self.processor.limits = limits;
+        self
+    }
+
+    /// Adds a mapper from errors to responses, tried after the ones added before it.
+    pub fn error_mapper(mut self, mapper: Arc<dyn ErrorMapper>) -> Self {
+        self.processor.error_mappers.push(mapper);
+        self
+    }
+
+    /// Every route with its methods, name and middleware.
+    pub fn route_table(&self) -> RouteTable {
+        self.processor.route_table()
+    }
+
+    /// Serves the route table on `GET pat`, see [`RouteTableEndPoint`].
+    pub fn serve_route_table(self, pat: &str) -> Self {
+        let mut methods = MethodSet::new();
+        methods.insert(Method::GET);
+        self.register(pat, Arc::new(RouteTableEndPoint), methods)
+    }
+
+    /// Serves the files of `dir` under `prefix`, see [`Router::serve_dir`];
+    /// panics like [`Self::register`].
+    pub fn serve_dir(mut self, prefix: &str, dir: StaticDir) -> Self {
+        if let Err(e) = self.processor.router.serve_dir(prefix, dir) {
+            panic!("cannot serve directory under {:?}: {}", prefix, e);
+        }
+        self
+    }
+
+    /// Attaches a middleware that wraps every request, before any route specific one.
+    pub fn middleware(mut self, mw: Arc<dyn Middleware>) -> Self {
+        self.processor.middlewares.push(mw);
+        self
+    }
+
+    /// Mounts a separately built route table under `prefix`, see [`Router::mount`];
+    /// panics in strict mode if its routes conflict.
+    pub fn mount(mut self, prefix: &str, router: Router) -> Self {
+        if let Err(e) = self.processor.router.mount(prefix, router) {
+            panic!("cannot mount routes under {:?}: {}", prefix, e);
+        }
         self
     }
 
+    /// Takes the route table out of the application, to be mounted into another one.
+    /// The application's own middleware moves onto each of its routes.
+    pub fn into_router(self) -> Router {
+        let mut router = self.processor.router;
+        router.wrap(&self.processor.middlewares);
+        router
+    }
+
     pub fn registrar(self) -> RouteRegistrar {
         RouteRegistrar::new(self)
     }
@@ -85,6 +369,7 @@ pub struct RouteRegistrar {
     app: Application,
     methods: MethodSet,
     prefix: String,
+    middlewares: Vec<Arc<dyn Middleware>>,
 }
 
 impl RouteRegistrar {
@@ -94,6 +379,7 @@ impl RouteRegistrar {
             app,
             methods: MethodSet::new(),
             prefix: String::new(),
+            middlewares: Vec::new(),
         }
     }
 
@@ -101,12 +387,39 @@ impl RouteRegistrar {
         self.app
     }
 
-    pub fn register<'b>(mut self, pat: &'b str, handle: Arc<dyn EndPoint>) -> Self{
-        self.app = self.app.register(format!("{}{}", self.prefix, pat).as_str(), handle, self.methods);
+    pub fn register(self, pat: &str, handle: Arc<dyn EndPoint>) -> Self{
+        self.register_with(pat, handle, Vec::new())
+    }
+
+    /// Registers a route with its own middleware, run inside the ones attached to this registrar.
+    pub fn register_with(mut self, pat: &str, handle: Arc<dyn EndPoint>, middlewares: Vec<Arc<dyn Middleware>>) -> Self{
+        let mut mws = self.middlewares.clone();
+        mws.extend(middlewares);
+        self.app = self.app.register_with(format!("{}{}", self.prefix, pat).as_str(), handle, self.methods.clone(), mws);
+        self
+    }
+
+    /// Registers a named route, see [`Router::url_for`].
+    pub fn register_named(mut self, name: &str, pat: &str, handle: Arc<dyn EndPoint>) -> Self{
+        self.app = self.app.register_named(name, format!("{}{}", self.prefix, pat).as_str(), handle, self.methods.clone(), self.middlewares.clone());
+        self
+    }
+
+    /// Mounts `router` under the current prefix followed by `pre`; the middleware
+    /// attached to this registrar wraps all of its routes.
+    pub fn mount(mut self, pre: &str, mut router: Router) -> Self {
+        router.wrap(&self.middlewares);
+        self.app = self.app.mount(format!("{}{}", self.prefix, pre).as_str(), router);
+        self
+    }
+
+    /// Attaches a middleware to every route registered through this registrar from now on.
+    pub fn middleware(mut self, mw: Arc<dyn Middleware>) -> Self {
+        self.middlewares.push(mw);
         self
     }
 
-    pub fn at<'b>(mut self, pre: &'b str) -> Self {
+    pub fn at(mut self, pre: &str) -> Self {
         self.prefix.push_str(pre);
         self
     }
@@ -151,4 +464,172 @@ impl RouteRegistrar {
         self
     }
 
+    pub fn connect(mut self) -> Self {
+        self.methods.insert(Method::CONNECT);
+        self
+    }
+
+    /// Adds any method, e.g. `.method(Method::from_str("PROPFIND").unwrap())`.
+    pub fn method(mut self, method: Method) -> Self {
+        self.methods.insert(method);
+        self
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{future::Future, pin::Pin};
+
+    use tokio::{io::{AsyncReadExt, AsyncWriteExt}, time::sleep};
+
+    use crate::{app::HttpResult, http::{HttpRequest, HttpResponse}};
+    use super::*;
+
+    async fn slow(_req: &mut HttpRequest<'_>, _captures: Vec<&'_ str>) -> HttpResult {
+        sleep(Duration::from_millis(200)).await;
+        Ok(HttpResponse::create_200_ok())
+    }
+
+    #[tokio::test]
+    async fn test_graceful_shutdown() {
+        let app = Application::new()
+            .handle_signals(false)
+            .drain_timeout(Duration::from_secs(5))
+            .registrar().get().register("/slow", crate::ep_wrap!(slow)).app()
+            .listen_tcp("127.0.0.1:0").await.unwrap();
+        let addr = app.listeners[0].local_addr().unwrap();
+        let shutdown = app.shutdown_handle();
+        let server = spawn(app.run());
+
+        let mut busy = TcpStream::connect(addr).await.unwrap();
+        let idle = TcpStream::connect(addr).await.unwrap();
+        busy.write_all(b"GET /slow HTTP/1.1\r\n\r\n").await.unwrap();
+        sleep(Duration::from_millis(50)).await;
+        shutdown.shutdown();
+
+        let mut resp = String::new();
+        busy.read_to_string(&mut resp).await.unwrap();
+        assert!(resp.starts_with("HTTP/1.1 200"), "{}", resp);
+        assert!(resp.contains("Connection: close\r\n"), "{}", resp);
+        timeout(Duration::from_secs(1), server).await.unwrap().unwrap();
+        drop(idle);
+        assert!(TcpStream::connect(addr).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_drain_timeout() {
+        let app = Application::new()
+            .handle_signals(false)
+            .drain_timeout(Duration::from_millis(50))
+            .registrar().get().register("/slow", crate::ep_wrap!(slow)).app()
+            .listen_tcp("127.0.0.1:0").await.unwrap();
+        let addr = app.listeners[0].local_addr().unwrap();
+        let shutdown = app.shutdown_handle();
+        let server = spawn(app.run());
+
+        let mut busy = TcpStream::connect(addr).await.unwrap();
+        busy.write_all(b"GET /slow HTTP/1.1\r\n\r\n").await.unwrap();
+        sleep(Duration::from_millis(20)).await;
+        shutdown.shutdown();
+        timeout(Duration::from_millis(150), server).await.unwrap().unwrap();
+        let mut resp = Vec::new();
+        // Closed without a response, possibly with a reset.
+        let _ = busy.read_to_end(&mut resp).await;
+        assert!(resp.is_empty());
+    }
+
+    async fn get(stream: &mut TcpStream) -> String {
+        stream.write_all(b"GET /slow HTTP/1.1\r\n\r\n").await.unwrap();
+        read_head(stream).await
+    }
+
+    /// Reads a response up to the end of its header; the bodies here are empty.
+    async fn read_head(stream: &mut TcpStream) -> String {
+        let mut resp = Vec::new();
+        while !resp.ends_with(b"\r\n\r\n") {
+            let mut byte = [0_u8; 1];
+            if timeout(Duration::from_secs(1), stream.read(&mut byte)).await.unwrap().unwrap() == 0 {
+                break;
+            }
+            resp.push(byte[0]);
+        }
+        String::from_utf8(resp).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_connection_admission() {
+        let app = Application::new()
+            .handle_signals(false)
+            .max_connections(1)
+            .at_capacity(AtCapacity::Reject)
+            .registrar().get().register("/slow", crate::ep_wrap!(slow)).app()
+            .listen_tcp("127.0.0.1:0").await.unwrap();
+        let addr = app.listeners[0].local_addr().unwrap();
+        let shutdown = app.shutdown_handle();
+        let server = spawn(app.run());
+        let mut first = TcpStream::connect(addr).await.unwrap();
+        assert!(get(&mut first).await.starts_with("HTTP/1.1 200"));
+        let mut second = TcpStream::connect(addr).await.unwrap();
+        assert!(get(&mut second).await.starts_with("HTTP/1.1 503"));
+        // A turned away request left unread does not reset the connection
+        // before its answer is read.
+        let mut third = TcpStream::connect(addr).await.unwrap();
+        third.write_all(format!("POST /slow HTTP/1.1\r\nContent-Length: 65536\r\n\r\n{}", "a".repeat(65536)).as_bytes()).await.unwrap();
+        sleep(Duration::from_millis(50)).await;
+        let mut resp = Vec::new();
+        third.read_to_end(&mut resp).await.unwrap();
+        assert!(resp.starts_with(b"HTTP/1.1 503"));
+        // The established connection is still served.
+        assert!(get(&mut first).await.starts_with("HTTP/1.1 200"));
+        shutdown.shutdown();
+        server.await.unwrap();
+
+        let app = Application::new()
+            .handle_signals(false)
+            .max_connections(1)
+            .registrar().get().register("/slow", crate::ep_wrap!(slow)).app()
+            .listen_tcp("127.0.0.1:0").await.unwrap();
+        let addr = app.listeners[0].local_addr().unwrap();
+        let shutdown = app.shutdown_handle();
+        let limit = app.connection_limit();
+        let server = spawn(app.run());
+        let mut first = TcpStream::connect(addr).await.unwrap();
+        assert!(get(&mut first).await.starts_with("HTTP/1.1 200"));
+        let mut second = TcpStream::connect(addr).await.unwrap();
+        second.write_all(b"GET /slow HTTP/1.1\r\n\r\n").await.unwrap();
+        let mut byte = [0_u8; 1];
+        assert!(timeout(Duration::from_millis(300), second.peek(&mut byte)).await.is_err());
+        limit.set_limit(2);
+        assert!(read_head(&mut second).await.starts_with("HTTP/1.1 200"));
+        shutdown.shutdown();
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_rejections_capped() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let admission = Admission {
+            limit: ConnectionLimit::new(0),
+            at_capacity: AtCapacity::Reject,
+            keep_alive: Duration::from_secs(5),
+            rejecting: Arc::new(Semaphore::new(1)),
+        };
+        let processor = Arc::new(Processor::new());
+        let count = Arc::new(AtomicU64::new(0));
+        let mut first = TcpStream::connect(addr).await.unwrap();
+        let (stream, _) = listener.accept().await.unwrap();
+        let rejecting = spawn(serve_connection(processor.clone(), count.clone(), admission.clone(), stream, None));
+        assert!(read_head(&mut first).await.starts_with("HTTP/1.1 503"));
+        // While the first one lingers, the next is closed without an answer.
+        let mut second = TcpStream::connect(addr).await.unwrap();
+        let (stream, _) = listener.accept().await.unwrap();
+        serve_connection(processor, count, admission, stream, None).await;
+        let mut resp = Vec::new();
+        let _ = second.read_to_end(&mut resp).await;
+        assert!(resp.is_empty());
+        drop(first);
+        rejecting.await.unwrap();
+    }
 }
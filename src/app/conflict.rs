@@ -0,0 +1,122 @@
+use std::{cmp::Ordering, collections::HashSet, fmt::Display};
+
+use super::{MethodSet, Pattern};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConflictKind {
+    /// The route matches nothing the other one does not match first, so it
+    /// only runs when the other handler answers `HandleError::NotFound`.
+    Unreachable,
+    /// Some urls match both routes and neither is more specific, so which one
+    /// runs depends on registration order.
+    Ambiguous,
+}
+
+/// Two routes of a router that accept the same method and can match the same url.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RouteConflict {
+    pub kind: ConflictKind,
+    /// The later registered route.
+    pub route: String,
+    pub other: String,
+    /// The methods both routes accept.
+    pub methods: MethodSet,
+}
+
+impl RouteConflict {
+
+    /// The same conflict inside a router mounted under `prefix`.
+    pub(crate) fn prefixed(mut self, prefix: &str) -> Self {
+        self.route.insert_str(0, prefix);
+        self.other.insert_str(0, prefix);
+        self
+    }
+
+}
+
+impl Display for RouteConflict {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let methods = self.methods.iter().map(|m| m.to_str().to_string()).collect::<Vec<_>>().join(", ");
+        match self.kind {
+            ConflictKind::Unreachable => write!(f, "route {:?} is shadowed by {:?} for {}", self.route, self.other, methods),
+            ConflictKind::Ambiguous => write!(f, "routes {:?} and {:?} overlap for {}", self.route, self.other, methods),
+        }
+    }
+}
+
+/// Combines the orders of two parts of the patterns; `None` when the parts disagree.
+fn combine(a: Option<Ordering>, b: Option<Ordering>) -> Option<Ordering> {
+    match (a?, b?) {
+        (Ordering::Equal, o) | (o, Ordering::Equal) => Some(o),
+        (x, y) if x == y => Some(x),
+        _ => None,
+    }
+}
+
+/// How `new` relates to `old`, a route registered before it: `None` when no
+/// url matches both or when the specificity order settles every overlap.
+///
+/// Only patterns alike in their literal text and placeholder types can
+/// conflict, as otherwise specificity picks the same route for every url.
+/// Within those, a constrained placeholder is more specific than a plain one
+/// and a query with more parameters more specific than one with a subset of
+/// them; anything else, such as two different regexes, is ambiguous.
+pub(crate) fn conflict_between(old: &Pattern, new: &Pattern) -> Option<ConflictKind> {
+    if old.url != new.url || old.holders.len() != new.holders.len()
+            || old.holders.iter().zip(&new.holders).any(|(a, b)| a.pos != b.pos || a.tp != b.tp) {
+        return None;
+    }
+
+    // `Less` when `new` is the more specific one.
+    let mut order = Some(Ordering::Equal);
+    for (a, b) in old.holders.iter().zip(&new.holders) {
+        let holder = match (&a.constraint, &b.constraint) {
+            (None, None) => Some(Ordering::Equal),
+            (Some(_), None) => Some(Ordering::Greater),
+            (None, Some(_)) => Some(Ordering::Less),
+            (Some(x), Some(y)) if x.as_str() == y.as_str() => Some(Ordering::Equal),
+            (Some(_), Some(_)) => None,
+        };
+        order = combine(order, holder);
+    }
+
+    let old_q: HashSet<_> = old.query.iter().filter(|q| q.restricts()).collect();
+    let new_q: HashSet<_> = new.query.iter().filter(|q| q.restricts()).collect();
+    let query = if old_q == new_q {
+        Some(Ordering::Equal)
+    } else if old_q.is_subset(&new_q) {
+        Some(Ordering::Less)
+    } else if new_q.is_subset(&old_q) {
+        Some(Ordering::Greater)
+    } else {
+        None
+    };
+
+    match combine(order, query) {
+        Some(Ordering::Equal) => Some(ConflictKind::Unreachable),
+        Some(_) => None,
+        None => Some(ConflictKind::Ambiguous),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn conflict(old: &str, new: &str) -> Option<ConflictKind> {
+        conflict_between(&Pattern::from_str(old).unwrap(), &Pattern::from_str(new).unwrap())
+    }
+
+    #[test]
+    fn test_conflicts() {
+        assert_eq!(conflict("/users/{id}", "/users/{name}"), Some(ConflictKind::Unreachable));
+        assert_eq!(conflict("/users/{id:int}", "/users/{id:[0-9a-f]+}"), Some(ConflictKind::Ambiguous));
+        assert_eq!(conflict("/users/{id:int}", "/users/{id}"), None);
+        assert_eq!(conflict("/search?q={}", "/search?page={}"), Some(ConflictKind::Ambiguous));
+        assert_eq!(conflict("/search?q={}", "/search?q={}&page={}"), None);
+        assert_eq!(conflict("/search?q={}", "/search?page={?}&q={}"), Some(ConflictKind::Unreachable));
+        assert_eq!(conflict("/{x:int}/{y}", "/{x}/{y:int}"), Some(ConflictKind::Ambiguous));
+        assert_eq!(conflict("/{path}", "/users/{id}"), None);
+        assert_eq!(conflict("/users/{}", "/users/{path}"), None);
+    }
+}
@@ -1,21 +1,169 @@
-use crate::http::ReqError;
+use std::fmt::Display;
+
+use crate::http::{limit_response, HttpResponse, ReqError, ResponseBody};
+
+use super::{MethodSet, RouteConflict};
 
 #[derive(Debug)]
 pub enum HandleError {
     IoError(std::io::Error),
     ReqError(ReqError),
+    /// The endpoint has nothing for this request; answered like an unmatched route.
+    NotFound,
+    /// Some route matched the url, but none accepts the method; holds the methods they accept.
+    MethodNotAllowed(MethodSet),
+}
+
+impl From<std::io::Error> for HandleError {
+    fn from(e: std::io::Error) -> Self {
+        HandleError::IoError(e)
+    }
+}
+
+impl From<ReqError> for HandleError {
+    fn from(e: ReqError) -> Self {
+        HandleError::ReqError(e)
+    }
 }
 
 #[derive(Debug)]
 pub enum RouteError {
     HandleError(HandleError),
     NotFound,
-    MethodNotAllowed,
+    /// Some route matched the url, but none accepts the method; holds the methods they accept.
+    MethodNotAllowed(MethodSet),
 }
 
 #[derive(Debug)]
 pub enum ProcError {
+    /// The request header could not be read.
     ReqError(ReqError),
     HandleError(HandleError),
     IoError(std::io::Error),
-}
\ No newline at end of file
+    /// A handler or middleware panicked; holds the panic message.
+    Panic(String),
+}
+
+impl ProcError {
+
+    /// The response sent for the error when no [`ErrorMapper`](super::ErrorMapper) has one:
+    /// 400 for a malformed request, 413, 414 or 431 for one over the limits,
+    /// 404 for [`HandleError::NotFound`], 405 with `Allow` for
+    /// [`HandleError::MethodNotAllowed`] and 500 for other handler failures.
+    pub fn response(&self) -> HttpResponse {
+        match self {
+            ProcError::ReqError(e) | ProcError::HandleError(HandleError::ReqError(e)) => match e {
+                ReqError::BadParam(msg) => {
+                    let mut resp = HttpResponse::create_400_bad_request();
+                    resp.body = ResponseBody::from(msg.clone());
+                    resp
+                },
+                e => limit_response(e).unwrap_or_else(HttpResponse::create_400_bad_request),
+            },
+            ProcError::HandleError(HandleError::NotFound) => HttpResponse::create_404_not_found(),
+            ProcError::HandleError(HandleError::MethodNotAllowed(allowed)) => {
+                let mut resp = HttpResponse::create_405_method_not_allowed();
+                resp.headers.insert("Allow".to_string(), allowed.to_allow());
+                resp
+            },
+            ProcError::HandleError(HandleError::IoError(_)) | ProcError::IoError(_) | ProcError::Panic(_) => HttpResponse::create_500_internal_server_error(),
+        }
+    }
+
+    /// Whether the connection has to be closed after the response, because
+    /// where the next request starts is unknown. That is the case for a
+    /// request header that could not be read and for a body that could not
+    /// be read to its end, and after a panic, which may have struck halfway
+    /// through the body; other handler failures leave the connection usable.
+    pub fn closes_connection(&self) -> bool {
+        match self {
+            ProcError::ReqError(_) | ProcError::IoError(_) | ProcError::Panic(_) => true,
+            ProcError::HandleError(HandleError::ReqError(e)) => !matches!(e, ReqError::BadParam(_) | ReqError::FmtError),
+            ProcError::HandleError(_) => false,
+        }
+    }
+
+}
+/// Why a route pattern was refused; positions are byte offsets into the pattern.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PatternError {
+    UnclosedBrace(usize),
+    UnexpectedBrace(usize),
+    /// Two placeholders with no literal text between them.
+    AdjacentHolders(usize),
+    InvalidName(String),
+    DuplicateName(String),
+    /// The constraint and why it does not compile.
+    InvalidConstraint(String, String),
+    InvalidQuery(String),
+}
+
+impl Display for PatternError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PatternError::UnclosedBrace(pos) => write!(f, "the `{{` at {} is never closed", pos),
+            PatternError::UnexpectedBrace(pos) => write!(f, "unexpected `}}` at {}", pos),
+            PatternError::AdjacentHolders(pos) => write!(f, "the placeholder at {} directly follows another one", pos),
+            PatternError::InvalidName(name) => write!(f, "invalid capture name `{}`", name),
+            PatternError::DuplicateName(name) => write!(f, "capture name `{}` is used twice", name),
+            PatternError::InvalidConstraint(c, e) => write!(f, "invalid constraint `{}`: {}", c, e),
+            PatternError::InvalidQuery(item) => write!(f, "invalid query parameter `{}`", item),
+        }
+    }
+}
+
+impl std::error::Error for PatternError {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RegisterError {
+    Pattern(PatternError),
+    /// Only in strict mode; the route conflicts with an earlier one.
+    Conflict(RouteConflict),
+    /// Another route of the router, or of a router mounted in it, already has this name.
+    DuplicateName(String),
+}
+
+impl From<PatternError> for RegisterError {
+    fn from(e: PatternError) -> Self {
+        RegisterError::Pattern(e)
+    }
+}
+
+impl Display for RegisterError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RegisterError::Pattern(e) => e.fmt(f),
+            RegisterError::Conflict(c) => c.fmt(f),
+            RegisterError::DuplicateName(name) => write!(f, "a route named `{}` already exists", name),
+        }
+    }
+}
+
+impl std::error::Error for RegisterError {}
+
+/// Why a url could not be built for a named route.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UrlError {
+    UnknownRoute(String),
+    MissingParam(String),
+    /// A parameter the pattern has no use for.
+    ExtraParam(String),
+    /// The parameter and the value that does not fit its placeholder.
+    InvalidParam(String, String),
+    /// The pattern has an anonymous placeholder at this position, which can not be filled.
+    AnonymousParam(usize),
+}
+
+impl Display for UrlError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UrlError::UnknownRoute(name) => write!(f, "no route named `{}`", name),
+            UrlError::MissingParam(name) => write!(f, "missing parameter `{}`", name),
+            UrlError::ExtraParam(name) => write!(f, "unknown parameter `{}`", name),
+            UrlError::InvalidParam(name, value) => write!(f, "invalid value {:?} for parameter `{}`", value, name),
+            UrlError::AnonymousParam(i) => write!(f, "placeholder {} has no name", i),
+        }
+    }
+}
+
+impl std::error::Error for UrlError {}
@@ -0,0 +1,25 @@
+use crate::http::{HttpRequestHeader, HttpResponse};
+
+use super::ProcError;
+
+/// Turns an error into the response sent for it, e.g. to give API routes
+/// JSON error bodies. `req` is `None` when the request header itself could
+/// not be read. Returning `None` leaves the error to the next mapper, and in
+/// the end to [`ProcError::response`].
+///
+/// Mappers see every error that reaches the [`Processor`](super::Processor),
+/// including unknown routes, refused methods and invalid url parameters.
+/// Whether the connection is kept does not depend on the mapper, see
+/// [`ProcError::closes_connection`].
+pub trait ErrorMapper: Send + Sync {
+    fn map(&self, err: &ProcError, req: Option<&HttpRequestHeader>) -> Option<HttpResponse>;
+}
+
+impl<F> ErrorMapper for F
+where
+    F: Fn(&ProcError, Option<&HttpRequestHeader>) -> Option<HttpResponse> + Send + Sync,
+{
+    fn map(&self, err: &ProcError, req: Option<&HttpRequestHeader>) -> Option<HttpResponse> {
+        self(err, req)
+    }
+}
@@ -0,0 +1,175 @@
+use std::{fmt::Write, sync::Arc};
+
+use async_trait::async_trait;
+
+use crate::http::{HttpRequest, HttpResponse};
+use super::{EndPoint, HandleError, HttpResult, MethodSet, Router};
+
+/// A route as registered, for listing what a service exposes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RouteInfo {
+    /// The pattern as written, behind the prefixes of the mounts it sits in.
+    pub pattern: String,
+    pub methods: MethodSet,
+    pub name: Option<String>,
+    /// The middleware around the handler, outermost first.
+    pub middlewares: Vec<String>,
+}
+
+/// Every route of an application, in registration order; the routes of a
+/// mounted router come where it was mounted.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RouteTable {
+    pub routes: Vec<RouteInfo>,
+}
+
+impl Router {
+
+    pub fn route_table(&self) -> RouteTable {
+        let routes = self.entry_routes().map(|(pat, route)| RouteInfo {
+            pattern: pat.as_str().to_string(),
+            methods: route.methods.clone(),
+            name: route.name.clone(),
+            middlewares: route.middlewares.iter().map(|mw| mw.name().to_string()).collect(),
+        });
+        RouteTable{routes: routes.collect()}
+    }
+
+}
+
+fn json_string(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c < ' ' => { let _ = write!(out, "\\u{:04x}", c as u32); },
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+impl RouteTable {
+
+    /// A JSON array with an object per route.
+    pub fn to_json(&self) -> String {
+        let mut out = String::from("[");
+        for (i, route) in self.routes.iter().enumerate() {
+            if i > 0 {
+                out.push(',');
+            }
+            out.push_str("{\"pattern\":");
+            json_string(&mut out, &route.pattern);
+            out.push_str(",\"methods\":[");
+            for (j, m) in route.methods.iter().enumerate() {
+                if j > 0 {
+                    out.push(',');
+                }
+                json_string(&mut out, m.to_str());
+            }
+            out.push_str("],\"name\":");
+            match &route.name {
+                Some(name) => json_string(&mut out, name),
+                None => out.push_str("null"),
+            }
+            out.push_str(",\"middlewares\":[");
+            for (j, mw) in route.middlewares.iter().enumerate() {
+                if j > 0 {
+                    out.push(',');
+                }
+                json_string(&mut out, mw);
+            }
+            out.push_str("]}");
+        }
+        out.push(']');
+        out
+    }
+
+    /// A line per route: methods, pattern, then the name and middleware if any.
+    pub fn to_text(&self) -> String {
+        let mut out = String::new();
+        for route in &self.routes {
+            let methods = route.methods.iter().map(|m| m.to_str().to_string()).collect::<Vec<_>>().join(",");
+            let _ = write!(out, "{:<12} {}", methods, route.pattern);
+            if let Some(name) = &route.name {
+                let _ = write!(out, "  name={}", name);
+            }
+            if !route.middlewares.is_empty() {
+                let _ = write!(out, "  middlewares={}", route.middlewares.join(","));
+            }
+            out.push('\n');
+        }
+        out
+    }
+
+}
+
+/// Serves the route table of the application, as JSON when the client asks
+/// for it with `?format=json` or prefers `application/json` in `Accept`, as
+/// plain text otherwise.
+///
+/// Register it on a path of your choice, e.g.
+/// `.register("/_routes", Arc::new(RouteTableEndPoint))`, preferably behind
+/// middleware that keeps it from the public.
+pub struct RouteTableEndPoint;
+
+#[async_trait]
+impl EndPoint for RouteTableEndPoint {
+    async fn handle<'a, 'b>(&self, req: &'a mut HttpRequest<'b>, _captures: Vec<&'b str>) -> HttpResult {
+        let table = req.extension::<Arc<RouteTable>>().ok_or(HandleError::NotFound)?;
+        let json = req.query().get("format") == Some("json")
+            || req.header.paras.accept().first().is_some_and(|(mime, _)| mime.eq_ignore_ascii_case("application/json"));
+        let mut resp = HttpResponse::create_200_ok();
+        if json {
+            resp.headers.insert("Content-Type".to_string(), "application/json".to_string());
+            resp.body = table.to_json().into();
+        } else {
+            resp.headers.insert("Content-Type".to_string(), "text/plain; charset=utf-8".to_string());
+            resp.body = table.to_text().into();
+        }
+        Ok(resp)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{app::{Middleware, Next}, http::Method};
+    use super::*;
+
+    struct Auth;
+
+    #[async_trait]
+    impl Middleware for Auth {
+        async fn handle<'a, 'b>(&self, req: &'a mut HttpRequest<'b>, captures: Vec<&'b str>, next: Next<'a>) -> HttpResult {
+            next.run(req, captures).await
+        }
+
+        fn name(&self) -> &str {
+            "auth"
+        }
+    }
+
+    #[test]
+    fn test_route_table() {
+        let mut get = MethodSet::new();
+        get.insert(Method::GET);
+        let mut admin = Router::new();
+        admin.register_named("user.show", "/users/{id:int}", Arc::new(RouteTableEndPoint), get.clone(), vec![Arc::new(Auth)]).unwrap();
+        let mut router = Router::new();
+        router.mount("/admin", admin).unwrap();
+        router.register("/say \"hi\"", Arc::new(RouteTableEndPoint), get).unwrap();
+
+        let table = router.route_table();
+        assert_eq!(table.routes[0].pattern, "/admin/users/{id:int}");
+        assert_eq!(table.routes[0].middlewares, vec!["auth"]);
+        assert_eq!(table.to_json(), concat!(
+            r#"[{"pattern":"/admin/users/{id:int}","methods":["GET"],"name":"user.show","middlewares":["auth"]},"#,
+            r#"{"pattern":"/say \"hi\"","methods":["GET"],"name":null,"middlewares":[]}]"#,
+        ));
+        assert_eq!(table.to_text(), "GET          /admin/users/{id:int}  name=user.show  middlewares=auth\nGET          /say \"hi\"\n");
+    }
+}
@@ -0,0 +1,130 @@
+use std::sync::{atomic::{AtomicUsize, Ordering}, Arc, Mutex};
+
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// What a new connection gets when the server already serves as many as
+/// its [`ConnectionLimit`] allows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AtCapacity {
+    /// Wait for another connection to close before reading any request.
+    Wait,
+    /// Answer 503 and close the connection; while many others are being
+    /// answered so, close it right away instead.
+    Reject,
+}
+
+#[derive(Debug)]
+struct LimitInner {
+    sem: Arc<Semaphore>,
+    limit: Mutex<usize>,
+    /// Permits in use beyond a lowered limit, given up as they come back.
+    owed: AtomicUsize,
+}
+
+/// How many connections are served at once. Clones share the limit, so it
+/// can be changed while the application runs; lowering it never drops a
+/// connection, it only delays the next ones until enough have closed.
+#[derive(Debug, Clone)]
+pub struct ConnectionLimit {
+    inner: Arc<LimitInner>,
+}
+
+/// Admission of one connection, handed back when dropped.
+#[derive(Debug)]
+pub(crate) struct ConnPermit {
+    permit: Option<OwnedSemaphorePermit>,
+    inner: Arc<LimitInner>,
+}
+
+impl Drop for ConnPermit {
+    fn drop(&mut self) {
+        if let Some(permit) = self.permit.take() {
+            if self.inner.owed.fetch_update(Ordering::AcqRel, Ordering::Acquire, |owed| owed.checked_sub(1)).is_ok() {
+                permit.forget();
+            }
+        }
+    }
+}
+
+impl ConnectionLimit {
+
+    pub fn new(limit: usize) -> Self {
+        ConnectionLimit {
+            inner: Arc::new(LimitInner {
+                sem: Arc::new(Semaphore::new(limit)),
+                limit: Mutex::new(limit),
+                owed: AtomicUsize::new(0),
+            }),
+        }
+    }
+
+    pub fn limit(&self) -> usize {
+        *self.inner.limit.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Connections that can still be admitted right away.
+    pub fn available(&self) -> usize {
+        self.inner.sem.available_permits()
+    }
+
+    pub fn set_limit(&self, new: usize) {
+        let mut limit = self.inner.limit.lock().unwrap_or_else(|e| e.into_inner());
+        if new > *limit {
+            let mut grow = new - *limit;
+            // Connections still over the old limit now fit again.
+            if let Ok(owed) = self.inner.owed.fetch_update(Ordering::AcqRel, Ordering::Acquire, |owed| Some(owed - owed.min(grow))) {
+                grow -= owed.min(grow);
+            }
+            self.inner.sem.add_permits(grow);
+        } else {
+            let shrink = *limit - new;
+            let forgotten = self.inner.sem.forget_permits(shrink);
+            self.inner.owed.fetch_add(shrink - forgotten, Ordering::AcqRel);
+        }
+        *limit = new;
+    }
+
+    pub(crate) async fn acquire(&self) -> ConnPermit {
+        // The semaphore is never closed.
+        let permit = self.inner.sem.clone().acquire_owned().await.ok();
+        ConnPermit { permit, inner: self.inner.clone() }
+    }
+
+    pub(crate) fn try_acquire(&self) -> Option<ConnPermit> {
+        let permit = self.inner.sem.clone().try_acquire_owned().ok()?;
+        Some(ConnPermit { permit: Some(permit), inner: self.inner.clone() })
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_connection_limit() {
+        let limit = ConnectionLimit::new(2);
+        let a = limit.try_acquire().unwrap();
+        let b = limit.try_acquire().unwrap();
+        assert!(limit.try_acquire().is_none());
+
+        limit.set_limit(1);
+        drop(a);
+        assert!(limit.try_acquire().is_none());
+        drop(b);
+        let c = limit.try_acquire().unwrap();
+        assert!(limit.try_acquire().is_none());
+
+        limit.set_limit(3);
+        assert_eq!(limit.available(), 2);
+        drop(c);
+        assert_eq!(limit.available(), 3);
+
+        let held: Vec<_> = (0..3).map(|_| limit.try_acquire().unwrap()).collect();
+        limit.set_limit(1);
+        limit.set_limit(2);
+        drop(held);
+        assert_eq!(limit.available(), 2);
+        assert_eq!(limit.limit(), 2);
+    }
+}
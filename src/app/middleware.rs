@@ -1 +1,210 @@
+use std::{future::Future, pin::Pin, sync::Arc};
 
+use async_trait::async_trait;
+
+use crate::http::HttpRequest;
+use super::{EndPoint, HttpResult};
+
+/// Code that runs around an [`EndPoint`].
+///
+/// A middleware may inspect or change the request (see
+/// [`HttpRequest::modify_header`] and [`HttpRequest::insert_extension`]), answer by itself without calling
+/// `next`, or call `next.run(req, captures)` and post-process the response.
+///
+/// Middleware runs outermost first: the ones attached to the
+/// [`Application`](super::Application), then those of the
+/// [`RouteRegistrar`](super::RouteRegistrar) prefix, then those of the route itself,
+/// each group in the order it was attached.
+#[async_trait]
+pub trait Middleware: Send + Sync + 'static {
+    async fn handle<'a, 'b>(&self, req: &'a mut HttpRequest<'b>, captures: Vec<&'b str>, next: Next<'a>) -> HttpResult;
+
+    /// How the route table lists this middleware; the type name by default.
+    fn name(&self) -> &str {
+        std::any::type_name::<Self>()
+    }
+}
+
+#[async_trait]
+impl<F> Middleware for F
+where
+    F: Send
+        + Sync
+        + 'static
+        + for<'a, 'b> Fn(
+            &'a mut HttpRequest<'b>,
+            Vec<&'b str>,
+            Next<'a>,
+        ) -> Pin<Box<dyn Future<Output = HttpResult> + 'a + Send>>,
+{
+    async fn handle<'a, 'b>(&self, req: &'a mut HttpRequest<'b>, captures: Vec<&'b str>, next: Next<'a>) -> HttpResult {
+        (self)(req, captures, next).await
+    }
+}
+
+/// A middleware listed under a name of its own, as `mw_wrap!` does for the
+/// functions it wraps.
+pub struct NamedMiddleware<M> {
+    name: &'static str,
+    inner: M,
+}
+
+impl<M: Middleware> NamedMiddleware<M> {
+
+    pub fn new(name: &'static str, inner: M) -> Self {
+        NamedMiddleware { name, inner }
+    }
+
+}
+
+#[async_trait]
+impl<M: Middleware> Middleware for NamedMiddleware<M> {
+    async fn handle<'a, 'b>(&self, req: &'a mut HttpRequest<'b>, captures: Vec<&'b str>, next: Next<'a>) -> HttpResult {
+        self.inner.handle(req, captures, next).await
+    }
+
+    fn name(&self) -> &str {
+        self.name
+    }
+}
+
+/// The rest of a middleware chain, ending at the endpoint.
+pub struct Next<'a> {
+    middlewares: &'a [Arc<dyn Middleware>],
+    endpoint: &'a dyn EndPoint,
+}
+
+impl<'a> Next<'a> {
+
+    pub fn new(middlewares: &'a [Arc<dyn Middleware>], endpoint: &'a dyn EndPoint) -> Self {
+        Next {
+            middlewares,
+            endpoint,
+        }
+    }
+
+    pub async fn run<'b>(self, req: &'a mut HttpRequest<'b>, captures: Vec<&'b str>) -> HttpResult {
+        match self.middlewares.split_first() {
+            Some((first, rest)) => first.handle(req, captures, Next::new(rest, self.endpoint)).await,
+            None => self.endpoint.handle(req, captures).await,
+        }
+    }
+
+}
+
+#[macro_export]
+macro_rules! mw_wrap{
+    ($f:expr) => {
+        {
+            fn mw_wrap_f<'a, 'b>(req: &'a mut HttpRequest<'b>, captures: Vec<&'b str>, next: Next<'a>) -> Pin<Box<dyn Future<Output = HttpResult> + 'a + Send>>{
+                Box::pin($f(req, captures, next))
+            }
+            Arc::new($crate::app::NamedMiddleware::new(stringify!($f), mw_wrap_f))
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use crate::{app::{MethodSet, Router}, http::{HeaderArena, HttpRequestHeader, HttpResponse, Method, ResponseBody}};
+    use super::*;
+
+    struct Trace(Vec<&'static str>);
+
+    struct Tag(&'static str);
+
+    #[async_trait]
+    impl Middleware for Tag {
+        async fn handle<'a, 'b>(&self, req: &'a mut HttpRequest<'b>, captures: Vec<&'b str>, next: Next<'a>) -> HttpResult {
+            let mut trace = req.remove_extension::<Trace>().unwrap_or(Trace(Vec::new()));
+            trace.0.push(self.0);
+            req.insert_extension(trace);
+            let mut resp = next.run(req, captures).await?;
+            let trace = format!("{}{}", resp.headers.get("X-Trace").unwrap_or_default(), self.0);
+            resp.headers.insert("X-Trace", trace);
+            Ok(resp)
+        }
+    }
+
+    async fn deny(_req: &mut HttpRequest<'_>, _captures: Vec<&'_ str>, _next: Next<'_>) -> HttpResult {
+        Ok(HttpResponse::create_403_forbidden())
+    }
+
+    struct Record(Mutex<Vec<&'static str>>);
+
+    #[async_trait]
+    impl EndPoint for Record {
+        async fn handle<'a, 'b>(&self, req: &'a mut HttpRequest<'b>, _captures: Vec<&'b str>) -> HttpResult {
+            *self.0.lock().unwrap() = req.extension::<Trace>().map(|t| t.0.clone()).unwrap_or_default();
+            Ok(HttpResponse::create_200_ok())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_middleware_order() {
+        let header = HttpRequestHeader::new();
+        let mut rx: &[u8] = b"";
+        let mut req = HttpRequest::new(&header, &mut rx);
+        let endpoint = Record(Mutex::new(Vec::new()));
+        let mws: Vec<Arc<dyn Middleware>> = vec![Arc::new(Tag("a")), Arc::new(Tag("b"))];
+        let resp = Next::new(&mws, &endpoint).run(&mut req, Vec::new()).await.unwrap();
+        assert_eq!(*endpoint.0.lock().unwrap(), vec!["a", "b"]);
+        assert_eq!(resp.headers.get("X-Trace"), Some("ba"));
+    }
+
+    #[tokio::test]
+    async fn test_middleware_short_circuit() {
+        let header = HttpRequestHeader::new();
+        let mut rx: &[u8] = b"";
+        let mut req = HttpRequest::new(&header, &mut rx);
+        let endpoint = Record(Mutex::new(vec!["untouched"]));
+        let mws: Vec<Arc<dyn Middleware>> = vec![Arc::new(Tag("a")), mw_wrap!(deny)];
+        let resp = Next::new(&mws, &endpoint).run(&mut req, Vec::new()).await.unwrap();
+        assert_eq!(resp.status_code, 403);
+        assert_eq!(*endpoint.0.lock().unwrap(), vec!["untouched"]);
+        assert_eq!(mws[0].name(), "webserver::app::middleware::tests::Tag");
+        assert_eq!(mws[1].name(), "deny");
+    }
+
+    async fn rewrite<'a, 'b>(req: &'a mut HttpRequest<'b>, captures: Vec<&'b str>, next: Next<'a>) -> HttpResult {
+        req.modify_header(|header| {
+            if let Some(rest) = header.url.strip_prefix("/v1") {
+                header.url = rest.to_string();
+            }
+            header.paras.insert("X-Api-Version", "1");
+        });
+        next.run(req, captures).await
+    }
+
+    struct Show;
+
+    #[async_trait]
+    impl EndPoint for Show {
+        async fn handle<'a, 'b>(&self, req: &'a mut HttpRequest<'b>, captures: Vec<&'b str>) -> HttpResult {
+            let mut resp = HttpResponse::create_200_ok();
+            resp.body = ResponseBody::from(format!("{} {:?} {:?}", req.header.url, req.header.get_para("X-Api-Version"), captures));
+            Ok(resp)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_middleware_modifies_request() {
+        let mut methods = MethodSet::new();
+        methods.insert(Method::GET);
+        let mut router = Router::new();
+        router.register("/users/{id}", Arc::new(Show), methods).unwrap();
+        let mut header = HttpRequestHeader::new();
+        header.url = "/v1/users/7".to_string();
+        let mut rx: &[u8] = b"";
+        let arena = HeaderArena::default();
+        let mut req = HttpRequest::new(&header, &mut rx);
+        req.set_header_arena(&arena);
+        let mws: Vec<Arc<dyn Middleware>> = vec![mw_wrap!(rewrite)];
+        let resp = Next::new(&mws, &router).run(&mut req, Vec::new()).await.unwrap();
+        let ResponseBody::Bytes(body) = resp.body else { panic!("expected a bytes body") };
+        assert_eq!(body, b"/users/7 Some(\"1\") [\"7\"]");
+        assert_eq!(header.url, "/v1/users/7");
+    }
+}
@@ -4,6 +4,11 @@ pub use pattern::*;
 mod router;
 pub use router::*;
 
+mod tree;
+
+mod conflict;
+pub use conflict::{ConflictKind, RouteConflict};
+
 mod endpoint;
 pub use endpoint::*;
 
@@ -13,8 +18,27 @@ pub use processor::*;
 mod error;
 pub use error::*;
 
+mod error_mapper;
+pub use error_mapper::*;
+
 mod middleware;
 pub use middleware::*;
 
+mod normalize;
+pub use normalize::*;
+
+mod introspect;
+pub use introspect::*;
+
+mod static_dir;
+pub use static_dir::*;
+
+mod shutdown;
+pub use shutdown::ShutdownHandle;
+
+mod limit;
+pub use limit::{AtCapacity, ConnectionLimit};
+
+#[allow(clippy::module_inception)]
 mod app;
 pub use app::{Application, RouteRegistrar};
@@ -0,0 +1,171 @@
+use async_trait::async_trait;
+
+use crate::http::{merge_slashes, normalize_percent, remove_dot_segments, HttpRequest, HttpRequestHeader, HttpResponse, Query, ReqError};
+
+use super::{EndPoint, HttpResult, Router};
+
+/// What to do when a url differs from a route only by a trailing `/`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrailingSlash {
+    /// `/hello` and `/hello/` are different urls.
+    Strict,
+    /// Answer with a 308 to the form a route is registered for.
+    Redirect,
+    /// Route to the form a route is registered for.
+    MatchBoth,
+}
+
+/// How request paths are cleaned up before routing.
+///
+/// By default escapes are normalized, dot segments resolved and runs of `/`
+/// merged, while trailing slashes are left as they are.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PathNormalization {
+    /// Decode escaped unreserved characters, see [`normalize_percent`].
+    /// A malformed escape is answered with 400.
+    pub decode_unreserved: bool,
+    pub merge_slashes: bool,
+    /// Resolve `.` and `..`, after `%2E` has been decoded when
+    /// `decode_unreserved` is set; `..` never climbs above the root.
+    pub resolve_dots: bool,
+    pub trailing_slash: TrailingSlash,
+}
+
+impl Default for PathNormalization {
+    fn default() -> Self {
+        PathNormalization {
+            decode_unreserved: true,
+            merge_slashes: true,
+            resolve_dots: true,
+            trailing_slash: TrailingSlash::Strict,
+        }
+    }
+}
+
+impl PathNormalization {
+
+    /// Cleans up a path, or `None` if it has a malformed escape.
+    pub fn normalize(&self, path: &str) -> Option<String> {
+        let mut path = path.to_string();
+        if self.decode_unreserved {
+            path = normalize_percent(&path)?;
+        }
+        if self.merge_slashes {
+            path = merge_slashes(&path);
+        }
+        if self.resolve_dots {
+            path = remove_dot_segments(&path);
+        }
+        Some(path)
+    }
+
+    /// Rewrites the url of `header` to its normalized form. Fails with
+    /// [`ReqError::FmtError`] for a malformed path; returns the endpoint to
+    /// run instead of the router for a trailing slash redirect.
+    pub(crate) fn apply(&self, header: &mut HttpRequestHeader, router: &Router) -> Result<Option<Redirect>, ReqError> {
+        // Leaves `*` and absolute urls alone.
+        if !header.url.starts_with('/') {
+            return Ok(None);
+        }
+        let mut path = self.normalize(header.path()).ok_or(ReqError::FmtError)?;
+        let query = header.query_str().map(|q| format!("?{}", q)).unwrap_or_default();
+
+        if self.trailing_slash != TrailingSlash::Strict && path != "/" {
+            let params = Query::parse(header.query_str().unwrap_or_default());
+            // The other form wins when its route is more specific, so that a
+            // catch-all does not keep `/hello/` from reaching `/hello`; it is
+            // not looked for when a route without placeholders has the path.
+            let here = router.find_at(&path, &params).map(|(route, _)| route);
+            let better = match here {
+                Some(here) if here.pat.holders.is_empty() => None,
+                here => {
+                    let alternate = match path.strip_suffix('/') {
+                        Some(trimmed) => trimmed.to_string(),
+                        None => format!("{}/", path),
+                    };
+                    let there = router.find_at(&alternate, &params).map(|(route, _)| route);
+                    let better = there.is_some_and(|there| here.is_none_or(|here| there.pat.cmp_specificity(&here.pat).is_lt()));
+                    better.then_some(alternate)
+                },
+            };
+            if let Some(alternate) = better {
+                if self.trailing_slash == TrailingSlash::Redirect {
+                    return Ok(Some(Redirect(format!("{}{}", alternate, query))));
+                }
+                path = alternate;
+            }
+        }
+
+        header.url = format!("{}{}", path, query);
+        Ok(None)
+    }
+
+}
+
+/// Answers with a 308 to the other trailing slash form of the url; run in
+/// place of the router, inside the middleware wrapping every request.
+pub(crate) struct Redirect(String);
+
+#[async_trait]
+impl EndPoint for Redirect {
+    async fn handle<'a, 'b>(&self, _req: &'a mut HttpRequest<'b>, _captures: Vec<&'b str>) -> HttpResult {
+        let mut resp = HttpResponse::create_308_permanent_redirect();
+        resp.headers.insert("Location".to_string(), self.0.clone());
+        Ok(resp)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{future::Future, pin::Pin, sync::Arc};
+
+    use crate::{app::{EndPoint, HttpResult, MethodSet}, http::{HttpRequest, Method}};
+    use super::*;
+
+    async fn ok(_req: &mut HttpRequest<'_>, _captures: Vec<&'_ str>) -> HttpResult {
+        Ok(HttpResponse::create_200_ok())
+    }
+
+    fn router() -> Router {
+        let mut get = MethodSet::new();
+        get.insert(Method::GET);
+        let handler: Arc<dyn EndPoint> = crate::ep_wrap!(ok);
+        let mut router = Router::new();
+        router.register("/hello", handler.clone(), get.clone()).unwrap();
+        router.register("/dir/", handler.clone(), get.clone()).unwrap();
+        router.register("/{path}", handler, get).unwrap();
+        router
+    }
+
+    fn apply(norm: &PathNormalization, url: &str) -> Result<String, (u32, Option<String>)> {
+        let mut header = HttpRequestHeader::new();
+        header.url = url.to_string();
+        match norm.apply(&mut header, &router()) {
+            Ok(None) => Ok(header.url),
+            Ok(Some(Redirect(location))) => Err((308, Some(location))),
+            Err(_) => Err((400, None)),
+        }
+    }
+
+    #[test]
+    fn test_normalization() {
+        let norm = PathNormalization::default();
+        assert_eq!(apply(&norm, "//a/./b/../c?x=/../"), Ok("/a/c?x=/../".to_string()));
+        assert_eq!(apply(&norm, "/static/%2e%2e/%2e%2E/etc/passwd"), Ok("/etc/passwd".to_string()));
+        assert_eq!(apply(&norm, "/static/..%2Fsecret"), Ok("/static/..%2Fsecret".to_string()));
+        assert_eq!(apply(&norm, "/hello/"), Ok("/hello/".to_string()));
+        assert_eq!(apply(&norm, "/bad%zz"), Err((400, None)));
+        assert_eq!(apply(&norm, "*"), Ok("*".to_string()));
+    }
+
+    #[test]
+    fn test_trailing_slash() {
+        let both = PathNormalization { trailing_slash: TrailingSlash::MatchBoth, ..Default::default() };
+        assert_eq!(apply(&both, "/hello/?a=1"), Ok("/hello?a=1".to_string()));
+        assert_eq!(apply(&both, "/dir"), Ok("/dir/".to_string()));
+        assert_eq!(apply(&both, "/other/"), Ok("/other/".to_string()));
+        let redirect = PathNormalization { trailing_slash: TrailingSlash::Redirect, ..Default::default() };
+        assert_eq!(apply(&redirect, "/hello/?a=1"), Err((308, Some("/hello?a=1".to_string()))));
+        assert_eq!(apply(&redirect, "/hello"), Ok("/hello".to_string()));
+    }
+}
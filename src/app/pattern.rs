@@ -1,6 +1,12 @@
-use std::collections::HashSet;
+use std::{cmp::{Ordering, Reverse}, collections::HashSet, sync::Arc};
 
-#[derive(Debug, PartialEq)]
+use regex::Regex;
+
+use crate::http::{form_decode, percent_encode, Query};
+
+use super::{PatternError, UrlError};
+
+#[derive(Debug, PartialEq, Clone)]
 pub(crate) enum PlaceHolderType {
     Path,
     Dir,
@@ -8,52 +14,254 @@ pub(crate) enum PlaceHolderType {
     All,
 }
 
+impl PlaceHolderType {
+
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "path" | "p" => Some(PlaceHolderType::Path),
+            "dir" | "d" => Some(PlaceHolderType::Dir),
+            "value" | "v" => Some(PlaceHolderType::Value),
+            "all" | "a" => Some(PlaceHolderType::All),
+            _ => None,
+        }
+    }
+
+    /// Bytes a capture of this type can not contain.
+    fn stops_at(&self, b: u8) -> bool {
+        match self {
+            PlaceHolderType::Path => b == b'?',
+            PlaceHolderType::Dir => b == b'/' || b == b'?',
+            PlaceHolderType::Value => b == b'&',
+            PlaceHolderType::All => false,
+        }
+    }
+
+}
+
+/// What a capture must look like, besides what its type allows.
+#[derive(Debug, Clone)]
+pub(crate) enum Constraint {
+    Int,
+    Uuid,
+    Slug,
+    Alpha,
+    /// A custom regex, or a glob such as `*.png`; anchored at both ends.
+    Regex(Regex),
+}
+
+impl Constraint {
+
+    /// `int`, `uuid`, `slug` and `alpha` are built in. Anything else is a
+    /// regex, except that text whose only special character is `*` is a
+    /// glob, so that `*.png` means what it looks like.
+    fn from_str(s: &str) -> Result<Self, PatternError> {
+        match s {
+            "int" => return Ok(Constraint::Int),
+            "uuid" => return Ok(Constraint::Uuid),
+            "slug" => return Ok(Constraint::Slug),
+            "alpha" => return Ok(Constraint::Alpha),
+            _ => {},
+        }
+        let is_glob = s.contains('*') && !s.contains(['[', ']', '(', ')', '{', '}', '+', '?', '^', '$', '|', '\\']);
+        let re = if is_glob {
+            s.split('*').map(regex::escape).collect::<Vec<_>>().join(".*")
+        } else {
+            s.to_string()
+        };
+        Regex::new(&format!("^(?:{})$", re))
+            .map(Constraint::Regex)
+            .map_err(|e| PatternError::InvalidConstraint(s.to_string(), e.to_string()))
+    }
+
+    /// The constraint as written, or the regex a glob became.
+    pub(crate) fn as_str(&self) -> &str {
+        match self {
+            Constraint::Int => "int",
+            Constraint::Uuid => "uuid",
+            Constraint::Slug => "slug",
+            Constraint::Alpha => "alpha",
+            Constraint::Regex(re) => re.as_str(),
+        }
+    }
+
+    fn matches(&self, s: &str) -> bool {
+        let b = s.as_bytes();
+        match self {
+            Constraint::Int => {
+                let digits = s.strip_prefix('-').unwrap_or(s);
+                !digits.is_empty() && digits.bytes().all(|c| c.is_ascii_digit())
+            },
+            Constraint::Uuid => {
+                b.len() == 36 && b.iter().enumerate().all(|(i, c)| match i {
+                    8 | 13 | 18 | 23 => *c == b'-',
+                    _ => c.is_ascii_hexdigit(),
+                })
+            },
+            Constraint::Slug => b.iter().all(|c| c.is_ascii_alphanumeric() || *c == b'-' || *c == b'_'),
+            Constraint::Alpha => b.iter().all(|c| c.is_ascii_alphabetic()),
+            Constraint::Regex(re) => re.is_match(s),
+        }
+    }
+
+}
+
+#[derive(Clone)]
 pub(crate) struct PlaceHolder {
     pub(crate) pos: usize,
     pub(crate) tp: PlaceHolderType,
+    pub(crate) constraint: Option<Constraint>,
 }
 
+impl PlaceHolder {
+
+    /// Lower is more specific: a constrained placeholder beats a plain one,
+    /// and `{dir}` beats `{value}`, `{path}` and `{all}`, in that order.
+    fn rank(&self) -> u8 {
+        let tp = match self.tp {
+            PlaceHolderType::Dir => 1,
+            PlaceHolderType::Value => 2,
+            PlaceHolderType::Path => 3,
+            PlaceHolderType::All => 4,
+        };
+        if self.constraint.is_some() { tp * 2 - 1 } else { tp * 2 }
+    }
+
+    /// Parses the text between the braces of a placeholder at `pos`:
+    /// `{}`, `{type}`, `{name}`, `{name:type}`, `{name:constraint}` or
+    /// `{name:type:constraint}`. A constrained placeholder is a `dir` unless
+    /// the type says otherwise.
+    fn from_str(s: &str, pos: usize) -> Result<(Option<String>, Self), PatternError> {
+        let (name, spec) = s.split_once(':').unwrap_or((s, ""));
+        let (name, tp, constraint) = if spec.is_empty() {
+            match PlaceHolderType::from_name(name) {
+                Some(tp) => ("", tp, None),
+                None => (name, PlaceHolderType::Dir, None),
+            }
+        } else {
+            let typed = spec.split_once(':').and_then(|(tp, c)| Some((PlaceHolderType::from_name(tp)?, c)));
+            match (PlaceHolderType::from_name(spec), typed) {
+                (Some(tp), _) => (name, tp, None),
+                (None, Some((tp, c))) => (name, tp, Some(Constraint::from_str(c)?)),
+                (None, None) => (name, PlaceHolderType::Dir, Some(Constraint::from_str(spec)?)),
+            }
+        };
+        if !name.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'_') {
+            return Err(PatternError::InvalidName(name.to_string()));
+        }
+        let name = if name.is_empty() { None } else { Some(name.to_string()) };
+        Ok((name, PlaceHolder{pos, tp, constraint}))
+    }
+
+}
+
+/// A query parameter a pattern asks for, in any position of the query string.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct QueryParam {
+    pub(crate) key: String,
+    pub(crate) required: bool,
+    /// A value the parameter must have, for `key=value`.
+    pub(crate) value: Option<String>,
+}
+
+impl QueryParam {
+
+    /// Parses one `&`-separated item of a pattern's query: `key` or `key={}`
+    /// for a required parameter, `key={?}` for an optional one, and
+    /// `key=value` for a fixed value. The braces may hold a name, e.g.
+    /// `{page?}`, which only documents the parameter.
+    fn from_str(item: &str) -> Result<Self, PatternError> {
+        let invalid = || PatternError::InvalidQuery(item.to_string());
+        let (key, value) = item.split_once('=').unwrap_or((item, "{}"));
+        let key = form_decode(key).ok_or_else(invalid)?;
+        if key.is_empty() || key.contains(['{', '}']) {
+            return Err(invalid());
+        }
+        match value.strip_prefix('{').and_then(|v| v.strip_suffix('}')) {
+            Some(holder) => {
+                let (name, required) = match holder.strip_suffix('?') {
+                    Some(name) => (name, false),
+                    None => (holder, true),
+                };
+                if !name.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'_') {
+                    return Err(invalid());
+                }
+                Ok(QueryParam{key, required, value: None})
+            },
+            None if value.contains(['{', '}']) => Err(invalid()),
+            None => Ok(QueryParam{key, required: true, value: Some(form_decode(value).ok_or_else(invalid)?)}),
+        }
+    }
+
+    /// Whether the parameter rules out some query strings; an optional one does not.
+    pub(crate) fn restricts(&self) -> bool {
+        self.required || self.value.is_some()
+    }
+
+    fn matches(&self, query: &Query) -> bool {
+        match &self.value {
+            Some(value) => query.get_all(&self.key).any(|v| v == value),
+            None => !self.required || query.contains(&self.key),
+        }
+    }
+
+}
+
+#[derive(Clone)]
 pub struct Pattern {
+    source: String,
+    /// The path part, with the placeholders cut out.
     pub(crate) url: String,
     pub(crate) holders: Vec<PlaceHolder>,
+    /// Capture names, by position; `None` for anonymous placeholders.
+    pub(crate) names: Arc<[Option<String>]>,
+    pub(crate) query: Vec<QueryParam>,
 }
 
 impl Pattern {
 
-    pub fn from_str(ps: &str) -> Option<Self> {
+    /// Parses a pattern. The path part is matched in order, with
+    /// placeholders capturing the variable parts; the query part after `?`
+    /// lists parameters the query string must have, in any order.
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(ps: &str) -> Result<Self, PatternError> {
         enum State {
             Normal,
             InBrace,
             Accept,
-            Deny,
         }
 
         let mut state = State::Normal;
-        let ps = ps.as_bytes();
+        let bytes = ps.as_bytes();
         let mut buf = Vec::<u8>::new();
         let mut holders = Vec::<PlaceHolder>::new();
         let mut i = 0_usize;
         let mut l = 0_usize;
-        let mut is_val: bool = false;
+        // Braces nest inside a placeholder, for regexes like `[0-9]{4}`.
+        let mut depth = 0_usize;
         let mut pos_set = HashSet::<usize>::new();
+        let mut names = Vec::<Option<String>>::new();
+        let mut query = Vec::new();
 
         loop {
             match state {
                 State::Normal => {
-                    if i == ps.len() {
+                    if i == bytes.len() {
                         state = State::Accept;
                         continue;
                     }
-                    match ps[i] {
+                    match bytes[i] {
                         b'{' => {
                             i += 1;
                             l = i;
+                            depth = 0;
                             state = State::InBrace;
                         },
+                        b'}' => {
+                            return Err(PatternError::UnexpectedBrace(i));
+                        },
                         b'?' => {
-                            is_val = true;
-                            buf.push(b'?');
-                            i += 1;
+                            query = ps[i + 1..].split('&').map(QueryParam::from_str).collect::<Result<_, _>>()?;
+                            state = State::Accept;
                         },
                         c => {
                             buf.push(c);
@@ -62,35 +270,31 @@ impl Pattern {
                     }
                 },
                 State::InBrace => {
-                    if i == ps.len() {
-                        state = State::Deny;
-                        continue;
+                    if i == bytes.len() {
+                        return Err(PatternError::UnclosedBrace(l - 1));
                     }
-                    match ps[i] {
+                    match bytes[i] {
+                        b'{' => {
+                            depth += 1;
+                            i += 1;
+                        },
+                        b'}' if depth > 0 => {
+                            depth -= 1;
+                            i += 1;
+                        },
                         b'}' => {
                             let pos = buf.len();
-                            
-                            if pos_set.contains(&pos) {
-                                state = State::Deny;
-                                continue;
+                            if !pos_set.insert(pos) {
+                                return Err(PatternError::AdjacentHolders(l - 1));
                             }
-                            pos_set.insert(pos);
-
-                            let tp = match &ps[l..i] {
-                                b"path" | b"p" => PlaceHolderType::Path,
-                                b"dir" | b"d" => PlaceHolderType::Dir,
-                                b"value" | b"v" => PlaceHolderType::Value,
-                                b"all" | b"a" => PlaceHolderType::All,
-                                _ => {
-                                    if is_val {
-                                        PlaceHolderType::Value
-                                    } else {
-                                        PlaceHolderType::Dir
-                                    }
-                                }
-                            };
-
-                            holders.push(PlaceHolder{pos, tp});
+
+                            let (name, holder) = PlaceHolder::from_str(&ps[l..i], pos)?;
+                            if name.is_some() && names.contains(&name) {
+                                return Err(PatternError::DuplicateName(name.unwrap_or_default()));
+                            }
+
+                            names.push(name);
+                            holders.push(holder);
 
                             state = State::Normal;
                             i += 1;
@@ -103,9 +307,6 @@ impl Pattern {
                 State::Accept => {
                     break;
                 },
-                State::Deny => {
-                    return None;
-                },
             }
         }
 
@@ -115,158 +316,171 @@ impl Pattern {
         }
 
         let ret = Pattern {
+            source: ps.to_string(),
             url,
             holders,
+            names: names.into(),
+            query,
         };  
 
-        Some(ret)
+        Ok(ret)
     }
 
-    pub fn match_url<'a>(&self, url: &'a str) -> Option<Vec<&'a str>> {
+    /// The pattern as it was written.
+    pub fn as_str(&self) -> &str {
+        &self.source
+    }
 
-        #[derive(Debug)]
-        enum State {
-            Normal,
-            Matching,
-            Accept,
-            Retry,
-            Deny,
-        }
+    /// Orders patterns by how specific they are, the most specific first.
+    ///
+    /// Patterns are compared from the left: at the first place they differ,
+    /// literal text beats a placeholder, a constrained placeholder beats a
+    /// plain one of its type, and `{dir}` beats `{value}`, `{path}` and
+    /// `{all}`, in that order. When one pattern runs out first, the longer
+    /// one is the more specific, so `/{path}/edit` beats `/{path}`. Patterns
+    /// alike up to the query prefer the one with more query parameters to
+    /// satisfy.
+    pub fn cmp_specificity(&self, other: &Self) -> Ordering {
+        let (mut a, mut b) = (self.ranks(), other.ranks());
+        let path = loop {
+            match (a.next(), b.next()) {
+                (Some(x), Some(y)) if x == y => continue,
+                (Some(x), Some(y)) => break x.cmp(&y),
+                (Some(_), None) => break Ordering::Less,
+                (None, Some(_)) => break Ordering::Greater,
+                (None, None) => break Ordering::Equal,
+            }
+        };
+        path
+            .then_with(|| Reverse(self.query.iter().filter(|q| q.restricts()).count())
+                .cmp(&Reverse(other.query.iter().filter(|q| q.restricts()).count())))
+    }
 
-        let mut ret = vec![&url[0..0]; self.holders.len()];
+    /// A rank per element of the path: 0 for each literal byte, then the
+    /// rank of each placeholder.
+    fn ranks(&self) -> impl Iterator<Item = u8> + '_ {
+        let mut last = 0_usize;
+        self.holders.iter()
+            .flat_map(move |h| {
+                let lits = h.pos - last;
+                last = h.pos;
+                std::iter::repeat_n(0, lits).chain(std::iter::once(h.rank()))
+            })
+            .chain(std::iter::repeat_n(0, self.url.len() - self.holders.last().map_or(0, |h| h.pos)))
+    }
 
-        let pat = self.url.as_bytes();
-        let url = url.as_bytes();
-        let mut i = 0_usize;
-        let mut j = 0_usize;
-        let mut place_nth = 0_usize;
-        let mut state = State::Normal;
-        let mut l = 0_usize; 
-        let mut r = 0_usize;
+    /// Builds a url this pattern matches, taking the placeholders and query
+    /// parameters from `params` by name. Values are percent-encoded, and must
+    /// suit their placeholder: not empty, without `/` for a `{dir}`, and
+    /// within the constraint if there is one. Fixed query values are added as
+    /// they are; every parameter must be used.
+    pub fn build_url(&self, params: &[(&str, &str)]) -> Result<String, UrlError> {
+        let mut used = vec![false; params.len()];
+        let mut take = |name: &str| {
+            let i = params.iter().position(|(k, _)| *k == name)?;
+            used[i] = true;
+            Some(params[i].1)
+        };
 
+        let mut url = String::new();
+        let mut last = 0_usize;
+        for (i, holder) in self.holders.iter().enumerate() {
+            url.push_str(&self.url[last..holder.pos]);
+            last = holder.pos;
+            let name = self.names[i].as_deref().ok_or(UrlError::AnonymousParam(i))?;
+            let value = take(name).ok_or_else(|| UrlError::MissingParam(name.to_string()))?;
+            let encoded = percent_encode(value, holder.tp != PlaceHolderType::Dir);
+            if value.is_empty() || (holder.tp == PlaceHolderType::Dir && value.contains('/'))
+                    || !holder.constraint.as_ref().is_none_or(|c| c.matches(&encoded)) {
+                return Err(UrlError::InvalidParam(name.to_string(), value.to_string()));
+            }
+            url.push_str(&encoded);
+        }
+        url.push_str(&self.url[last..]);
 
-        loop {
-            match state {
-                State::Normal => {
-                    if !self.holders.is_empty() && place_nth == self.holders.len() {
-                        state = State::Deny;
-                        continue;
-                    }
-                    if !self.holders.is_empty() && self.holders[place_nth].pos == i {
-                        if l < r {
-                            unsafe {
-                                ret[place_nth] = std::str::from_utf8_unchecked(&url[l..r]);
-                            }
-                            place_nth += 1;
-                        }
-                        l = j;
-                        r = j;
-                        state = State::Matching;
-                        continue;
-                    }
+        let mut query = Vec::new();
+        for param in &self.query {
+            let value = match (&param.value, take(&param.key)) {
+                (Some(fixed), None) => fixed.as_str(),
+                (Some(fixed), Some(value)) if value == fixed => value,
+                (Some(_), Some(value)) => return Err(UrlError::InvalidParam(param.key.clone(), value.to_string())),
+                (None, Some(value)) => value,
+                (None, None) if param.required => return Err(UrlError::MissingParam(param.key.clone())),
+                (None, None) => continue,
+            };
+            query.push(format!("{}={}", percent_encode(&param.key, false), percent_encode(value, false)));
+        }
+        if let Some(i) = used.iter().position(|u| !u) {
+            return Err(UrlError::ExtraParam(params[i].0.to_string()));
+        }
+        if !query.is_empty() {
+            url.push('?');
+            url.push_str(&query.join("&"));
+        }
+        Ok(url)
+    }
 
-                    if j == url.len() {
-                        if i == pat.len() {
-                            state = State::Accept;
-                            continue;
-                        } else {
-                            state = State::Deny;
-                            continue;
-                        }
-                    } else if i == pat.len() {
-                        state = State::Retry;
-                        continue;
-                    }
-                    
-                    if pat[i] == url[j] {
-                        i += 1;
-                        j += 1;
-                    } else {
-                        state = State::Retry;
-                        continue;
-                    }
-                },
-                State::Matching => {
-                    if j == url.len() {
-                        if i == pat.len() {
-                            r = j;
-                            if l < r {
-                                state = State::Accept;
-                                continue;
-                            } else {
-                                state = State::Deny;
-                                continue;
-                            }
-                        } else {
-                            state = State::Deny;
-                            continue;
-                        }
-                    }
+    /// Names of the captures, in the order `match_url` returns them.
+    pub fn names(&self) -> &[Option<String>] {
+        &self.names
+    }
 
-                    if !self.holders.is_empty() && place_nth == self.holders.len() {
-                        state = State::Deny;
-                        continue;
-                    }
-                    if match self.holders[place_nth].tp {
-                        PlaceHolderType::Path => {
-                            url[j] == b'?'
-                        },
-                        PlaceHolderType::Dir => {
-                            url[j] == b'/' || url[j] == b'?'
-                        },
-                        PlaceHolderType::Value => {
-                            url[j] == b'&'
-                        },
-                        PlaceHolderType::All => false,
-                    } {
-                        r = j;
-                        if l < r {
-                            unsafe {
-                                ret[place_nth] = std::str::from_utf8_unchecked(&url[l..r]);
-                            }
-                            place_nth += 1;
-                        }
-                        l = r;
-                        i += 1;
-                        j += 1;
-                        state = State::Normal;
-                    } else if i != pat.len() && pat[i] == url[j] {
-                        r = j;
-                        i += 1;
-                        j += 1;
-                        state = State::Normal;
-                    } else {
-                        j += 1;
-                    }
+    /// Whether `query` has the parameters the pattern asks for.
+    pub fn match_query(&self, query: &Query) -> bool {
+        self.query.iter().all(|param| param.matches(query))
+    }
 
-                },
-                State::Accept => {
-                    if l < r {
-                        if place_nth == self.holders.len() {
-                            state = State::Deny;
-                            continue;
-                        }
-                        unsafe {
-                            ret[place_nth] = std::str::from_utf8_unchecked(&url[l..r]);
-                        }
-                    }
-                    return Some(ret);
-                },
-                State::Retry => {
-                    if l >= r {
-                        state = State::Deny;
-                    } else {
-                        i = self.holders[place_nth].pos;
-                        j = r + 1;
-                        state = State::Matching;
-                    }
-                },
-                State::Deny => {
+    /// Matches the path of `url` and checks its query string; the captures
+    /// are those of the path.
+    pub fn match_url<'a>(&self, url: &'a str) -> Option<Vec<&'a str>> {
+        let (url, query) = url.split_once('?').unwrap_or((url, ""));
+        if !self.query.is_empty() && !self.match_query(&Query::parse(query)) {
+            return None;
+        }
+        self.match_path(url).filter(|captures| self.match_constraints(captures))
+    }
+
+    /// Whether the path captures satisfy the constraints of their placeholders.
+    pub(crate) fn match_constraints(&self, captures: &[&str]) -> bool {
+        self.holders.iter().zip(captures).all(|(holder, cap)| {
+            holder.constraint.as_ref().is_none_or(|c| c.matches(cap))
+        })
+    }
+
+    /// Matches the path `url`. Each placeholder captures the shortest run of
+    /// the bytes its type allows that the literal text after it follows, the
+    /// last one up to the end of the url; an earlier capture is never
+    /// lengthened once the next placeholder is reached. That makes matching
+    /// linear in the url for a given pattern.
+    pub(crate) fn match_path<'a>(&self, url: &'a str) -> Option<Vec<&'a str>> {
+        let pat = self.url.as_bytes();
+        let bytes = url.as_bytes();
+        let lead = self.holders.first().map_or(pat.len(), |h| h.pos);
+        if !bytes.starts_with(&pat[..lead]) {
+            return None;
+        }
+        if self.holders.is_empty() {
+            return (bytes.len() == pat.len()).then(Vec::new);
+        }
+        let mut captures = Vec::with_capacity(self.holders.len());
+        let mut j = lead;
+        for (k, holder) in self.holders.iter().enumerate() {
+            let next = self.holders.get(k + 1);
+            let lit = &pat[holder.pos..next.map_or(pat.len(), |h| h.pos)];
+            let mut e = j;
+            loop {
+                if e == bytes.len() || holder.tp.stops_at(bytes[e]) {
                     return None;
-                },
+                }
+                e += 1;
+                if url.is_char_boundary(e) && bytes[e..].starts_with(lit) && (next.is_some() || e + lit.len() == bytes.len()) {
+                    break;
+                }
             }
+            captures.push(&url[j..e]);
+            j = e + lit.len();
         }
-
+        Some(captures)
     }
 }
 
@@ -287,10 +501,11 @@ mod tests {
     #[test]
     fn test_pattern_dir_value() {
         let pt = Pattern::from_str("/hello/{}?val={}").unwrap();
-        assert_eq!(pt.url, "/hello/?val=");
+        assert_eq!(pt.url, "/hello/");
+        assert_eq!(pt.holders.len(), 1);
         assert_eq!(pt.holders[0].tp, PlaceHolderType::Dir);
-        assert_eq!(pt.holders[1].tp, PlaceHolderType::Value);
-        assert_eq!(pt.match_url("/hello/world?val=123"), Some(vec!["world", "123"]));
+        assert_eq!(pt.match_url("/hello/world?val=123"), Some(vec!["world"]));
+        assert_eq!(pt.match_url("/hello/world?other=1"), None);
         assert_eq!(pt.match_url("/hello/world/evil?val=123"), None);
     }
 
@@ -308,23 +523,111 @@ mod tests {
     #[test]
     fn test_pattern_dir_path_values() {
         let pt = Pattern::from_str("/hello/{d}/{path}?val={}&age={}").unwrap();
-        assert_eq!(pt.url, "/hello//?val=&age=");
+        assert_eq!(pt.url, "/hello//");
+        assert_eq!(pt.holders[0].tp, PlaceHolderType::Dir);
+        assert_eq!(pt.holders[1].tp, PlaceHolderType::Path);
+        assert_eq!(pt.match_url("/hello/world/from?val=123&age=24"), Some(vec!["world", "from"]));
+        assert_eq!(pt.match_url("/hello/world/from/evil?age=24&val=123"), Some(vec!["world", "from/evil"]));
+        assert_eq!(pt.match_url("/hello/world/from/evil/rust?val=1%202&x=y&age=24"), Some(vec!["world", "from/evil/rust"]));
+        assert_eq!(pt.match_url("/hello/world/from?val=123"), None);
+    }
+
+    #[test]
+    fn test_pattern_literal_after_holders() {
+        let pt = Pattern::from_str("/users/{}/posts").unwrap();
+        assert_eq!(pt.match_url("/users/7/posts"), Some(vec!["7"]));
+        assert_eq!(pt.match_url("/users/7/posts/1"), None);
+        let pt = Pattern::from_str("/{path}/edit").unwrap();
+        assert_eq!(pt.match_url("/a/b/edit"), Some(vec!["a/b"]));
+        assert_eq!(pt.match_url("/a/edit/b/edit"), Some(vec!["a/edit/b"]));
+        let pt = Pattern::from_str("/{path}/{path}").unwrap();
+        assert_eq!(pt.match_url("/a/b/c/d"), Some(vec!["a", "b/c/d"]));
+        let pt = Pattern::from_str("/files/{}.{}").unwrap();
+        assert_eq!(pt.match_url("/files/a.tar.gz"), Some(vec!["a", "tar.gz"]));
+        assert_eq!(pt.match_url("/files/a/b.png"), None);
+    }
+
+    #[test]
+    fn test_pattern_query() {
+        let pt = Pattern::from_str("/search?q={}&page={page?}&sort=name&debug").unwrap();
+        assert_eq!(pt.query.len(), 4);
+        assert!(pt.match_url("/search?sort=name&q=a+b&debug").is_some());
+        assert!(pt.match_url("/search?debug=1&q=&page=2&sort=date&sort=name").is_some());
+        assert!(pt.match_url("/search?q=a&sort=date&debug").is_none());
+        assert!(pt.match_url("/search?q=a&sort=name").is_none());
+        assert!(pt.match_url("/search/x?q=a&sort=name&debug").is_none());
+        assert!(Pattern::from_str("/search?={}").is_err());
+        assert!(Pattern::from_str("/search?q={a-b}").is_err());
+        assert!(Pattern::from_str("/search?q=x{}").is_err());
+    }
+
+    #[test]
+    fn test_pattern_named() {
+        let pt = Pattern::from_str("/users/{id}/files/{rest:path}").unwrap();
+        assert_eq!(pt.url, "/users//files/");
         assert_eq!(pt.holders[0].tp, PlaceHolderType::Dir);
         assert_eq!(pt.holders[1].tp, PlaceHolderType::Path);
-        assert_eq!(pt.holders[2].tp, PlaceHolderType::Value);
-        assert_eq!(pt.holders[3].tp, PlaceHolderType::Value);
-        assert_eq!(pt.match_url("/hello/world/from?val=123&age=24"), Some(vec!["world", "from", "123", "24"]));
-        assert_eq!(pt.match_url("/hello/world/from/evil?val=123&age=24"), Some(vec!["world", "from/evil", "123", "24"]));
-        assert_eq!(pt.match_url("/hello/world/from/evil/rust?val=123&age=24"), Some(vec!["world", "from/evil/rust", "123", "24"]));
+        assert_eq!(pt.names(), &[Some("id".to_string()), Some("rest".to_string())]);
+        let pt = Pattern::from_str("/{}/{d}/{id:dir}").unwrap();
+        assert_eq!(pt.names(), &[None, None, Some("id".to_string())]);
+        assert!(Pattern::from_str("/{id}/{id}").is_err());
+        assert_eq!(Pattern::from_str("/{id}/{id}").err(), Some(PatternError::DuplicateName("id".to_string())));
+        assert_eq!(Pattern::from_str("/{a-b}").err(), Some(PatternError::InvalidName("a-b".to_string())));
+    }
+
+    #[test]
+    fn test_pattern_constraints() {
+        let pt = Pattern::from_str("/users/{id:int}").unwrap();
+        assert_eq!(pt.match_url("/users/42"), Some(vec!["42"]));
+        assert_eq!(pt.match_url("/users/bob"), None);
+        let pt = Pattern::from_str("/files/{name:*.png}").unwrap();
+        assert_eq!(pt.match_url("/files/cat.png"), Some(vec!["cat.png"]));
+        assert_eq!(pt.match_url("/files/cat.png.txt"), None);
+        assert_eq!(pt.match_url("/files/a/cat.png"), None);
+        let pt = Pattern::from_str("/archive/{year:[0-9]{4}}/{rest:path:.*\\.txt}").unwrap();
+        assert_eq!(pt.match_url("/archive/2024/a/b.txt"), Some(vec!["2024", "a/b.txt"]));
+        assert_eq!(pt.match_url("/archive/24/a/b.txt"), None);
+        assert_eq!(pt.match_url("/archive/2024/a/b.md"), None);
+        let pt = Pattern::from_str("/{:uuid}/{s:slug}/{a:alpha}").unwrap();
+        assert!(pt.match_url("/123e4567-e89b-12d3-a456-426614174000/my-post/abc").is_some());
+        assert!(pt.match_url("/123e4567-e89b-12d3-a456-42661417400/my-post/abc").is_none());
+        assert!(pt.match_url("/123e4567-e89b-12d3-a456-426614174000/my post/abc").is_none());
+        assert!(pt.match_url("/123e4567-e89b-12d3-a456-426614174000/my-post/ab1").is_none());
+        assert!(matches!(Pattern::from_str("/{id:[0-9]{4}"), Err(PatternError::UnclosedBrace(1))));
+        assert!(matches!(Pattern::from_str("/{id:[0-9}"), Err(PatternError::InvalidConstraint(..))));
+        assert!(matches!(Pattern::from_str("/a}"), Err(PatternError::UnexpectedBrace(2))));
     }
 
     #[test]
     fn test_pattern_fail() {
-        assert!(Pattern::from_str("/hello/{d}/{path}?val={}{}&age={}").is_none());
-        assert!(Pattern::from_str("/hello/{d}{}/{path}?val={}&age={}").is_none());
+        assert!(Pattern::from_str("/hello/{d}/{path}?val={}{}&age={}").is_err());
+        assert_eq!(Pattern::from_str("/hello/{d}{}/{path}?val={}&age={}").err(), Some(PatternError::AdjacentHolders(10)));
         let pt = Pattern::from_str("/hello").unwrap();
         assert_eq!(pt.match_url("/hello"), Some(vec![]));
         assert_eq!(pt.match_url("/hell0"), None);
     }
 
+
+    #[test]
+    fn test_build_url() {
+        let pt = Pattern::from_str("/users/{id:int}/files/{rest:path}?sort=name&page={?}").unwrap();
+        assert_eq!(pt.build_url(&[("id", "7"), ("rest", "a b/c.txt")]).unwrap(), "/users/7/files/a%20b/c.txt?sort=name");
+        assert_eq!(pt.build_url(&[("rest", "x"), ("page", "2"), ("id", "7")]).unwrap(), "/users/7/files/x?sort=name&page=2");
+        assert_eq!(pt.build_url(&[("id", "7")]), Err(UrlError::MissingParam("rest".to_string())));
+        assert_eq!(pt.build_url(&[("id", "x"), ("rest", "a")]), Err(UrlError::InvalidParam("id".to_string(), "x".to_string())));
+        assert_eq!(pt.build_url(&[("id", "7"), ("rest", "a"), ("x", "1")]), Err(UrlError::ExtraParam("x".to_string())));
+        let pt = Pattern::from_str("/tags/{tag}/{}").unwrap();
+        assert_eq!(pt.build_url(&[("tag", "a/b")]), Err(UrlError::InvalidParam("tag".to_string(), "a/b".to_string())));
+        assert_eq!(pt.build_url(&[("tag", "a")]), Err(UrlError::AnonymousParam(1)));
+    }
+
+    #[test]
+    fn test_specificity_longer() {
+        let cmp = |a: &str, b: &str| Pattern::from_str(a).unwrap().cmp_specificity(&Pattern::from_str(b).unwrap());
+        assert_eq!(cmp("/{path}/edit", "/{path}"), Ordering::Less);
+        assert_eq!(cmp("/{path}", "/{path}/edit"), Ordering::Greater);
+        assert_eq!(cmp("/files/{}.png", "/files/{}"), Ordering::Less);
+        assert_eq!(cmp("/files/{}", "/files/{}.png"), Ordering::Greater);
+        assert_eq!(cmp("/files/{}", "/files/{}"), Ordering::Equal);
+    }
 }
@@ -1,7 +1,9 @@
-use tokio::{io::{AsyncBufRead, AsyncWrite, BufReader}, net::tcp::{ReadHalf, WriteHalf}};
-use crate::http::{HttpRequest, HttpRequestHeader, HttpResponse, ReqError};
+use std::{any::Any, panic::AssertUnwindSafe, sync::{Arc, OnceLock}};
+use futures::FutureExt;
+use tokio::io::{AsyncBufRead, AsyncWrite};
+use crate::http::{HeaderArena, HttpRequest, HttpRequestHeader, HttpResponse, Method, ReqError, RequestLimits};
 
-use super::{ProcError, RouteError, Router};
+use super::{EndPoint, ErrorMapper, HandleError, Middleware, Next, PathNormalization, ProcError, RouteTable, Router, ShutdownHandle, Urls};
 
 pub enum ConnectionState {
     Opening,
@@ -14,6 +16,32 @@ pub struct ProcRes {
 
 pub struct Processor {
     pub router: Router,
+    /// Middleware wrapping every request, including the ones no route matches.
+    pub middlewares: Vec<Arc<dyn Middleware>>,
+    /// The named routes, collected when the first request comes in.
+    urls: OnceLock<Arc<Urls>>,
+    routes: OnceLock<Arc<RouteTable>>,
+    /// Applied to the url of every request before routing.
+    pub normalization: PathNormalization,
+    pub limits: RequestLimits,
+    /// Tried in order for the response to an error, see [`ErrorMapper`].
+    pub error_mappers: Vec<Arc<dyn ErrorMapper>>,
+    /// Once shut down, every response closes its connection.
+    pub shutdown: ShutdownHandle,
+}
+
+/// The message `panic!` was given, when it is a string.
+fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    match payload.downcast::<String>() {
+        Ok(msg) => *msg,
+        Err(payload) => payload.downcast_ref::<&str>().map(|msg| msg.to_string()).unwrap_or_else(|| "non-string panic payload".to_string()),
+    }
+}
+
+impl Default for Processor {
+    fn default() -> Self {
+        Processor::new()
+    }
 }
 
 impl Processor{
@@ -21,45 +49,125 @@ impl Processor{
     pub fn new() -> Self {
         Processor {
             router: Router::new(),
+            middlewares: Vec::new(),
+            urls: OnceLock::new(),
+            routes: OnceLock::new(),
+            normalization: PathNormalization::default(),
+            limits: RequestLimits::default(),
+            error_mappers: Vec::new(),
+            shutdown: ShutdownHandle::new(),
         }
     }
 
+    /// The response to `err`, from the first mapper that has one, marked to
+    /// close the connection when the error calls for it.
+    pub fn error_response(&self, err: &ProcError, req: Option<&HttpRequestHeader>) -> HttpResponse {
+        let mut resp = self.error_mappers.iter()
+            .find_map(|mapper| mapper.map(err, req))
+            .unwrap_or_else(|| err.response());
+        if err.closes_connection() {
+            resp.headers.insert("Connection", "close");
+        }
+        resp
+    }
+
+    /// The routes of the router, each with the middleware wrapping every request in front of its own.
+    pub fn route_table(&self) -> RouteTable {
+        let mut table = self.router.route_table();
+        let global: Vec<String> = self.middlewares.iter().map(|mw| mw.name().to_string()).collect();
+        for route in &mut table.routes {
+            route.middlewares.splice(0..0, global.iter().cloned());
+        }
+        table
+    }
+
     pub async fn handle(&self, buf_rx: &mut (impl AsyncBufRead + Send + Unpin), tx: &mut (impl AsyncWrite + Unpin)) -> Result<ProcRes, ProcError> {
-        let req_header = match HttpRequestHeader::from_async_stream(buf_rx).await {
+        let mut req_header = match HttpRequestHeader::from_async_stream_with(buf_rx, &self.limits).await {
             Err(ReqError::EmptyReq) => {
                 return Ok(ProcRes {connect_state: ConnectionState::Closed});
             },
+            // Nobody is left to answer.
+            Err(ReqError::IOError(e)) => {
+                return Err(ProcError::ReqError(ReqError::IOError(e)));
+            },
             Err(e) => {
-                return Err(ProcError::ReqError(e));
+                let e = ProcError::ReqError(e);
+                println!("Request Error: {:?}", e);
+                let mut resp = self.error_response(&e, None);
+                resp.write_to(tx).await.map_err(|e| { ProcError::IoError(e) })?;
+                return Ok(ProcRes {connect_state: ConnectionState::Closed});
             },
             Ok(req_header) => req_header,
         };
+        let normalized = self.normalization.apply(&mut req_header, &self.router);
+        let arena = HeaderArena::default();
         let mut req =  HttpRequest::new(&req_header, buf_rx);
-        let mut resp = match self.router.routing(&mut req).await {
-            Ok(resp) => resp,
-            Err(RouteError::MethodNotAllowed) => {
-                HttpResponse::create_405_method_not_allowed()
+        req.set_header_arena(&arena);
+        req.set_body_limit(&self.limits);
+        req.insert_extension(self.urls.get_or_init(|| Arc::new(self.router.urls())).clone());
+        req.insert_extension(self.routes.get_or_init(|| Arc::new(self.route_table())).clone());
+        let mut resp = match normalized {
+            Err(e) => {
+                let e = ProcError::HandleError(HandleError::ReqError(e));
+                println!("Request Error: {:?}", e);
+                self.error_response(&e, Some(req.header))
             },
-            Err(RouteError::NotFound) => {
-                HttpResponse::create_404_not_found()
+            Ok(redirect) => {
+                let endpoint: &dyn EndPoint = match &redirect {
+                    Some(redirect) => redirect,
+                    None => &self.router,
+                };
+                match AssertUnwindSafe(Next::new(&self.middlewares, endpoint).run(&mut req, Vec::new())).catch_unwind().await {
+                    Ok(Ok(resp)) => resp,
+                    Ok(Err(e)) => {
+                        let e = ProcError::HandleError(e);
+                        println!("Handle Error: {:?}", e);
+                        self.error_response(&e, Some(req.header))
+                    },
+                    Err(payload) => {
+                        let e = ProcError::Panic(panic_message(payload));
+                        let route = self.router.find(&req.header.url).map(|(route, _)| route.pat.as_str());
+                        println!("Handler Panic: {:?} in route {:?} for {} {}", e, route, req.header.method, req.header.url);
+                        self.error_response(&e, Some(req.header))
+                    },
+                }
             },
-            Err(RouteError::HandleError(e)) => {
-                return Err(ProcError::HandleError(e));
-            }
         };
-        resp.version = req.header.version;
-        resp.write_to(tx).await.map_err(|e| { ProcError::IoError(e) })?;
+        // The response answers the request as it was received, whatever the
+        // middleware rewrote it to. Handlers of unsafe methods evaluate their
+        // preconditions themselves, before acting.
+        if matches!(req_header.method, Method::GET | Method::HEAD) {
+            resp = resp.check_preconditions(&req_header);
+        }
+        resp.version = req_header.version;
+        if self.shutdown.is_shutting_down() {
+            resp.headers.insert("Connection", "close");
+        }
+        if req_header.method == Method::HEAD {
+            resp.write_head_to(tx).await.map_err(|e| { ProcError::IoError(e) })?;
+        } else {
+            resp.write_to(tx).await.map_err(|e| { ProcError::IoError(e) })?;
+            if resp.is_close_delimited() {
+                return Ok(ProcRes {connect_state: ConnectionState::Closed});
+            }
+        }
+        // Whatever is left of the request is not read, e.g. after a 413, a
+        // body that failed to parse, or during shutdown.
+        if resp.headers.connection_has("close") {
+            return Ok(ProcRes {connect_state: ConnectionState::Closed});
+        }
+        req.drain_body().await.map_err(|e| { ProcError::ReqError(e) })?;
         let mut ret  = ProcRes {connect_state: ConnectionState::Opening};
-        match req.header.version {
+        match req_header.version {
             crate::http::HttpVersion::HTTP1_0 => {
-                if req.header.paras.get("Connection") == Some(&"Keep-Alive".to_string()) {
+                if req_header.paras.connection_has("keep-alive") {
                     ret.connect_state = ConnectionState::Opening;
                 } else {
                     ret.connect_state = ConnectionState::Closed;
                 }
             },
             crate::http::HttpVersion::HTTP1_1 => {
-                if req.header.paras.get("Connection") == Some(&"close".to_string()) {
+                if req_header.paras.connection_has("close") {
                     ret.connect_state = ConnectionState::Closed;
                 } else {
                     ret.connect_state = ConnectionState::Opening;
@@ -69,4 +177,171 @@ impl Processor{
         Ok(ret)
     }
 
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{future::Future, pin::Pin};
+
+    use crate::{app::{HandleError, HttpResult, MethodSet}, http::ResponseBody};
+    use super::*;
+
+    async fn boom(_req: &mut HttpRequest<'_>, _captures: Vec<&'_ str>) -> HttpResult {
+        panic!("boom")
+    }
+
+    async fn fail(_req: &mut HttpRequest<'_>, _captures: Vec<&'_ str>) -> HttpResult {
+        Err(HandleError::IoError(std::io::Error::other("disk on fire")))
+    }
+
+    async fn echo(req: &mut HttpRequest<'_>, _captures: Vec<&'_ str>) -> HttpResult {
+        let mut resp = HttpResponse::create_200_ok();
+        resp.body = ResponseBody::from(req.body_string().await?);
+        Ok(resp)
+    }
+
+    fn processor() -> Processor {
+        let mut methods = MethodSet::new();
+        methods.insert(Method::GET);
+        methods.insert(Method::POST);
+        let mut processor = Processor::new();
+        processor.router.register("/fail", crate::ep_wrap!(fail), methods.clone()).unwrap();
+        processor.router.register("/api/fail", crate::ep_wrap!(fail), methods.clone()).unwrap();
+        processor.router.register("/echo", crate::ep_wrap!(echo), methods.clone()).unwrap();
+        processor.router.register("/boom", crate::ep_wrap!(boom), methods).unwrap();
+        processor
+    }
+
+    async fn send(processor: &Processor, mut raw: &[u8]) -> (String, bool) {
+        let mut tx = Vec::new();
+        let res = processor.handle(&mut raw, &mut tx).await.unwrap();
+        (String::from_utf8(tx).unwrap(), matches!(res.connect_state, ConnectionState::Opening))
+    }
+
+    #[tokio::test]
+    async fn test_error_responses() {
+        let processor = processor();
+        let (resp, open) = send(&processor, b"NOT A REQUEST\r\n\r\n").await;
+        assert!(resp.starts_with("HTTP/1.1 400"), "{}", resp);
+        assert!(resp.contains("Connection: close\r\n"));
+        assert!(!open);
+
+        let (resp, open) = send(&processor, b"GET /fail HTTP/1.1\r\n\r\n").await;
+        assert!(resp.starts_with("HTTP/1.1 500"), "{}", resp);
+        assert!(open);
+
+        let (resp, open) = send(&processor, b"POST /echo HTTP/1.1\r\nContent-Length: 2\r\n\r\n\xff\xfe").await;
+        assert!(resp.starts_with("HTTP/1.1 400"), "{}", resp);
+        assert!(open);
+
+        let (resp, open) = send(&processor, b"POST /echo HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\nzz\r\n").await;
+        assert!(resp.starts_with("HTTP/1.1 400"), "{}", resp);
+        assert!(!open);
+
+        let (resp, open) = send(&processor, b"POST /echo HTTP/1.1\r\nContent-Length: +5\r\n\r\nhello").await;
+        assert!(resp.starts_with("HTTP/1.1 400"), "{}", resp);
+        assert!(!open);
+
+        let mut processor = processor;
+        processor.limits = RequestLimits { max_request_line: 32, max_header_line: 32, max_body: 4, ..Default::default() };
+        let over: [(&[u8], &str); 5] = [
+            (b"GET /echo/0123456789012345678901234567890 HTTP/1.1\r\n\r\n", "414"),
+            (b"GET /echo HTTP/1.1\r\nX-Long: 01234567890123456789012345678\r\n\r\n", "431"),
+            (b"POST /echo HTTP/1.1\r\nContent-Length: 5\r\n\r\nhello", "413"),
+            (b"POST /echo HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n0\r\n\r\n", "413"),
+            (b"POST /echo HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n0\r\nX-Long: 01234567890123456789012345678\r\n\r\n", "413"),
+        ];
+        for (raw, status) in over {
+            let (resp, open) = send(&processor, raw).await;
+            assert!(resp.starts_with(&format!("HTTP/1.1 {}", status)), "{}", resp);
+            assert!(resp.contains("Connection: close\r\n"), "{}", resp);
+            assert!(!open);
+        }
+    }
+
+    async fn seen<'a, 'b>(req: &'a mut HttpRequest<'b>, captures: Vec<&'b str>, next: Next<'a>) -> HttpResult {
+        let mut resp = next.run(req, captures).await?;
+        resp.headers.insert("X-Seen", "1");
+        Ok(resp)
+    }
+
+    #[tokio::test]
+    async fn test_normalization_responses() {
+        let mut processor = processor();
+        processor.normalization.trailing_slash = crate::app::TrailingSlash::Redirect;
+        processor.middlewares.push(crate::mw_wrap!(seen));
+        processor.error_mappers.push(Arc::new(|err: &ProcError, _req: Option<&HttpRequestHeader>| {
+            let mut resp = err.response();
+            resp.headers.insert("X-Mapped", "1");
+            Some(resp)
+        }));
+        let (resp, open) = send(&processor, b"GET /echo/?a=1 HTTP/1.1\r\n\r\n").await;
+        assert!(resp.starts_with("HTTP/1.1 308"), "{}", resp);
+        assert!(resp.contains("Location: /echo?a=1\r\n"), "{}", resp);
+        assert!(resp.contains("X-Seen: 1\r\n"), "{}", resp);
+        assert!(open);
+        let (resp, open) = send(&processor, b"GET /bad%zz HTTP/1.1\r\n\r\n").await;
+        assert!(resp.starts_with("HTTP/1.1 400"), "{}", resp);
+        assert!(resp.contains("X-Mapped: 1\r\n"), "{}", resp);
+        assert!(open);
+    }
+
+    async fn rewrite<'a, 'b>(req: &'a mut HttpRequest<'b>, captures: Vec<&'b str>, next: Next<'a>) -> HttpResult {
+        req.modify_header(|header| {
+            header.method = Method::HEAD;
+            header.paras.insert("Connection", "close");
+        });
+        next.run(req, captures).await
+    }
+
+    #[tokio::test]
+    async fn test_rewritten_header() {
+        let mut processor = processor();
+        processor.middlewares.push(crate::mw_wrap!(rewrite));
+        let (resp, open) = send(&processor, b"POST /echo HTTP/1.1\r\nContent-Length: 2\r\n\r\nhi").await;
+        assert!(resp.starts_with("HTTP/1.1 200"), "{}", resp);
+        assert!(resp.ends_with("\r\n\r\nhi"), "{}", resp);
+        assert!(open);
+    }
+
+    #[tokio::test]
+    async fn test_panic() {
+        let processor = processor();
+        let (resp, open) = send(&processor, b"GET /boom HTTP/1.1\r\n\r\n").await;
+        assert!(resp.starts_with("HTTP/1.1 500"), "{}", resp);
+        assert!(resp.contains("Connection: close\r\n"));
+        assert!(!open);
+        let (resp, _) = send(&processor, b"GET /fail HTTP/1.1\r\n\r\n").await;
+        assert!(resp.starts_with("HTTP/1.1 500"), "{}", resp);
+    }
+
+    #[tokio::test]
+    async fn test_error_mappers() {
+        let mut processor = processor();
+        processor.error_mappers.push(Arc::new(|err: &ProcError, req: Option<&HttpRequestHeader>| {
+            if !req?.path().starts_with("/api/") {
+                return None;
+            }
+            let mut resp = err.response();
+            let body = format!("{{\"status\":{}}}", resp.status_code);
+            resp.headers.insert("Content-Type", "application/json");
+            resp.headers.insert("Content-Length", body.len().to_string());
+            resp.body = ResponseBody::from(body);
+            Some(resp)
+        }));
+        let (resp, _) = send(&processor, b"GET /api/fail HTTP/1.1\r\n\r\n").await;
+        assert!(resp.starts_with("HTTP/1.1 500"), "{}", resp);
+        assert!(resp.ends_with("{\"status\":500}"), "{}", resp);
+        let (resp, _) = send(&processor, b"GET /fail HTTP/1.1\r\n\r\n").await;
+        assert!(resp.ends_with("\r\n\r\n"), "{}", resp);
+        // Unmatched routes reach the mappers too.
+        let (resp, open) = send(&processor, b"GET /api/missing HTTP/1.1\r\n\r\n").await;
+        assert!(resp.starts_with("HTTP/1.1 404"), "{}", resp);
+        assert!(resp.ends_with("{\"status\":404}"), "{}", resp);
+        assert!(open);
+        let (resp, _) = send(&processor, b"DELETE /api/fail HTTP/1.1\r\n\r\n").await;
+        assert!(resp.starts_with("HTTP/1.1 405"), "{}", resp);
+        assert!(resp.contains("Allow: GET, POST, HEAD, OPTIONS\r\n"), "{}", resp);
+        assert!(resp.ends_with("{\"status\":405}"), "{}", resp);
+    }
+}
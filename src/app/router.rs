@@ -1,42 +1,134 @@
-use std::sync::Arc;
-use crate::http::{HttpRequest, HttpResponse, Method};
-use super::{EndPoint, Pattern, RouteError};
+use std::{collections::HashMap, sync::Arc};
+use async_trait::async_trait;
+use crate::http::{ExtensionMethod, HttpRequest, HttpResponse, Method, Query};
+use super::{conflict::conflict_between, tree::RouteTree, EndPoint, HandleError, HttpResult, Middleware, Next, Pattern, RegisterError, RouteConflict, RouteError, UrlError};
 
-#[derive(Clone, Copy)]
+/// A set of methods: a bit per standard method, and a list for extension methods.
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct MethodSet {
-    bits: u8,
+    bits: u16,
+    ext: Vec<ExtensionMethod>,
+}
+
+impl Default for MethodSet {
+    fn default() -> Self {
+        MethodSet::new()
+    }
 }
 
 impl MethodSet {
 
     pub fn new() -> Self{
         MethodSet{
-            bits: 0_u8
+            bits: 0_u16,
+            ext: Vec::new(),
         }
     }
 
+    fn bit(method: Method) -> u16 {
+        method.standard_index().map_or(0, |i| 1_u16 << i)
+    }
+
     pub fn insert(&mut self, method: Method) {
-        self.bits |= 1_u8 << (method as u8);
+        match method {
+            Method::Extension(ext) if !self.ext.contains(&ext) => self.ext.push(ext),
+            Method::Extension(_) => {},
+            m => self.bits |= Self::bit(m),
+        }
     }
 
     pub fn remove(&mut self, method: Method) {
-        self.bits &= !(1_u8 << (method as u8));
+        match method {
+            Method::Extension(ext) => self.ext.retain(|e| *e != ext),
+            m => self.bits &= !Self::bit(m),
+        }
     }
 
     pub fn contains(&self, method: Method) -> bool {
-        self.bits & (1_u8 << (method as u8)) != 0
+        match method {
+            Method::Extension(ext) => self.ext.contains(&ext),
+            m => self.bits & Self::bit(m) != 0,
+        }
+    }
+
+    pub fn union(&mut self, other: &MethodSet) {
+        self.bits |= other.bits;
+        for ext in &other.ext {
+            self.insert(Method::Extension(*ext));
+        }
+    }
+
+    /// The methods in both sets.
+    pub fn intersection(&self, other: &MethodSet) -> MethodSet {
+        MethodSet {
+            bits: self.bits & other.bits,
+            ext: self.ext.iter().filter(|ext| other.ext.contains(ext)).copied().collect(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bits == 0 && self.ext.is_empty()
+    }
+
+    /// Standard methods first, then extension methods in insertion order.
+    pub fn iter(&self) -> impl Iterator<Item = Method> + '_ {
+        Method::STANDARD
+            .into_iter()
+            .filter(|m| self.contains(*m))
+            .chain(self.ext.iter().map(|ext| Method::Extension(*ext)))
+    }
+
+    /// The value of an `Allow` header listing these methods, together with
+    /// the ones the router answers by itself: `HEAD` wherever `GET` is allowed, and `OPTIONS`.
+    pub fn to_allow(&self) -> String {
+        let mut methods = self.clone();
+        if methods.contains(Method::GET) {
+            methods.insert(Method::HEAD);
+        }
+        methods.insert(Method::OPTIONS);
+        methods.iter().map(|m| m.to_str().to_string()).collect::<Vec<_>>().join(", ")
     }
 }
 
 pub struct Route {
+    /// The name `url_for` knows the route by, if any.
+    pub name: Option<String>,
     pub pat: Pattern,
     pub methods: MethodSet,
+    pub middlewares: Vec<Arc<dyn Middleware>>,
     pub next: Arc<dyn EndPoint>,
 }
 
+/// A router mounted under a fixed path prefix.
+pub struct Mount {
+    pub prefix: String,
+    pub router: Router,
+}
+
+/// A route as the router matches it: its own routes and those of mounted
+/// routers alike, the latter with the mount prefix in front of the pattern.
+struct Entry {
+    pat: Pattern,
+    /// The mount the route sits in, if not in this router.
+    at: Option<usize>,
+    /// The index of the route, or of the entry in the mounted router.
+    index: usize,
+}
+
 pub struct Router {
-    pub routes: Vec<Route>,
-    // pub sub: HashMap<&'static str, Router>,
+    pub(crate) routes: Vec<Route>,
+    /// Every route that can be dispatched to, in registration order.
+    entries: Vec<Entry>,
+    tree: RouteTree,
+    pub(crate) sub: Vec<Mount>,
+    conflicts: Vec<RouteConflict>,
+    strict: bool,
+}
+
+impl Default for Router {
+    fn default() -> Self {
+        Router::new()
+    }
 }
 
 impl Router {
@@ -44,28 +136,597 @@ impl Router {
     pub fn new() -> Self {
         Router {
             routes: Vec::new(),
+            entries: Vec::new(),
+            tree: RouteTree::default(),
+            sub: Vec::new(),
+            conflicts: Vec::new(),
+            strict: false,
+        }
+    }
+
+    /// In strict mode, registering or mounting routes that conflict with
+    /// earlier ones fails instead of only being recorded in [`Self::conflicts`].
+    /// Turning it on fails as well when conflicts are already recorded.
+    pub fn set_strict(&mut self, strict: bool) -> Result<&mut Self, RegisterError> {
+        if let Some(conflict) = self.conflicts.iter().find(|_| strict) {
+            return Err(RegisterError::Conflict(conflict.clone()));
         }
+        self.strict = strict;
+        Ok(self)
+    }
+
+    /// Conflicts found while registering and mounting, including those inside mounted routers.
+    pub fn conflicts(&self) -> Vec<RouteConflict> {
+        self.conflicts.clone()
+    }
+
+    /// The route of entry `e`, which may sit in a mounted router.
+    fn route(&self, e: usize) -> &Route {
+        let entry = &self.entries[e];
+        match entry.at {
+            None => &self.routes[entry.index],
+            Some(m) => self.sub[m].router.route(entry.index),
+        }
+    }
+
+    /// Every route with its pattern behind the prefixes of the mounts it sits in, in registration order.
+    pub(crate) fn entry_routes(&self) -> impl Iterator<Item = (&Pattern, &Route)> + '_ {
+        self.entries.iter().enumerate().map(|(e, entry)| (&entry.pat, self.route(e)))
+    }
+
+    /// The entries matching `path` and `query`, most specific first and in
+    /// registration order among equals.
+    fn matches<'u>(&self, path: &'u str, query: &Query) -> Vec<(usize, Vec<&'u str>)> {
+        let mut matches: Vec<_> = self.tree.lookup(path, |e| &self.entries[e].pat).into_iter()
+            .filter(|(e, captures)| self.entries[*e].pat.match_constraints(captures) && self.entries[*e].pat.match_query(query))
+            .collect();
+        matches.sort_by(|(a, _), (b, _)| self.entries[*a].pat.cmp_specificity(&self.entries[*b].pat));
+        matches
     }
 
     pub async fn routing(&self, req: &mut HttpRequest<'_>) -> Result<HttpResponse, RouteError> {
-        for route in &self.routes {
-            if let Some(captures) = route.pat.match_url(&req.header.url) {
-                if !route.methods.contains(req.header.method) {
-                    return Err(RouteError::MethodNotAllowed);
+        let header = req.header;
+        self.routing_at(req, header.path()).await
+    }
+
+    /// Routes `path`, the path of the request url; the query string is
+    /// checked against the patterns separately.
+    ///
+    /// The routes of mounted routers are ranked together with the router's
+    /// own, by the specificity of their patterns with the mount prefix in front.
+    pub async fn routing_at<'b>(&self, req: &mut HttpRequest<'b>, path: &'b str) -> Result<HttpResponse, RouteError> {
+        // Every matching route gets a chance before the method is refused,
+        // so `GET /x` and `POST /x` may be registered as separate routes.
+        // `HEAD` falls back on the `GET` routes when no route registers it.
+        let method = req.header.method;
+        let matches = self.matches(path, req.query());
+        // Only routes refusing the method make up `Allow`.
+        let mut allowed = MethodSet::new();
+        let mut candidates = Vec::new();
+        for (i, captures) in &matches {
+            let methods = &self.route(*i).methods;
+            if methods.contains(method) {
+                candidates.push((*i, captures.clone()));
+            } else if !(method == Method::HEAD && methods.contains(Method::GET)) {
+                allowed.union(methods);
+            }
+        }
+        if method == Method::HEAD {
+            for (i, captures) in matches {
+                let methods = &self.route(i).methods;
+                if !methods.contains(Method::HEAD) && methods.contains(Method::GET) {
+                    candidates.push((i, captures));
                 }
-                let res = route.next.handle(req, captures).await
-                                    .map_err(|e| { RouteError::HandleError(e) })?;
-                return Ok(res);
             }
         }
-        Err(RouteError::NotFound)
+        // A route accepting the method had nothing for the url, e.g. a
+        // missing file, which is not a matter of the method.
+        let accepted = !candidates.is_empty();
+        for (i, captures) in candidates {
+            let route = self.route(i);
+            req.set_url_paras(route.pat.names.clone(), captures.clone());
+            match Next::new(&route.middlewares, &*route.next).run(req, captures).await {
+                Ok(res) => return Ok(res),
+                Err(HandleError::NotFound) => {},
+                Err(e) => return Err(RouteError::HandleError(e)),
+            }
+        }
+        if accepted || allowed.is_empty() {
+            Err(RouteError::NotFound)
+        } else {
+            Err(RouteError::MethodNotAllowed(allowed))
+        }
+    }
+
+    /// Every method registered on any route, including mounted ones.
+    pub fn all_methods(&self) -> MethodSet {
+        let mut all = MethodSet::new();
+        for route in &self.routes {
+            all.union(&route.methods);
+        }
+        for mount in &self.sub {
+            all.union(&mount.router.all_methods());
+        }
+        all
+    }
+
+    /// The route `routing_at` would dispatch `url` to, ignoring the method.
+    pub fn find<'u>(&self, url: &'u str) -> Option<(&Route, Vec<&'u str>)> {
+        let (path, query) = url.split_once('?').unwrap_or((url, ""));
+        self.find_at(path, &Query::parse(query))
+    }
+
+    pub(crate) fn find_at<'u>(&self, path: &'u str, query: &Query) -> Option<(&Route, Vec<&'u str>)> {
+        let (e, captures) = self.matches(path, query).into_iter().next()?;
+        Some((self.route(e), captures))
+    }
+
+    /// Builds the url of the route named `name`, see [`Pattern::build_url`].
+    /// Routes of mounted routers get the mount prefix in front.
+    pub fn url_for(&self, name: &str, params: &[(&str, &str)]) -> Result<String, UrlError> {
+        let pat = self.entry_routes()
+            .find_map(|(pat, route)| (route.name.as_deref() == Some(name)).then_some(pat))
+            .ok_or_else(|| UrlError::UnknownRoute(name.to_string()))?;
+        pat.build_url(params)
+    }
+
+    /// Every named route, for building urls without the router, see [`Urls`].
+    /// Names are unique across mounts, see [`Self::mount`].
+    pub fn urls(&self) -> Urls {
+        let routes = self.entry_routes()
+            .filter_map(|(pat, route)| Some((route.name.clone()?, pat.clone())))
+            .collect();
+        Urls{routes}
+    }
+
+    /// Mounts `router` under `prefix`. Its routes see the path after the
+    /// prefix, so a route `""` matches the prefix itself and `"/users"` matches
+    /// `{prefix}/users`. A trailing `/` on the prefix is ignored.
+    ///
+    /// The conflicts of `router` are taken over, and its routes are checked
+    /// against those already here like registered ones. A route name already
+    /// in use here is refused with [`RegisterError::DuplicateName`].
+    pub fn mount(&mut self, prefix: &str, router: Router) -> Result<&mut Self, RegisterError> {
+        if let Some(name) = router.entry_routes().find_map(|(_, r)| r.name.as_deref().filter(|name| self.has_name(name))) {
+            return Err(RegisterError::DuplicateName(name.to_string()));
+        }
+        let prefix = prefix.trim_end_matches('/');
+        let mut conflicts: Vec<_> = router.conflicts.iter().map(|c| c.clone().prefixed(prefix)).collect();
+        let mut entries = Vec::new();
+        for (index, entry) in router.entries.iter().enumerate() {
+            let pat = Pattern::from_str(&format!("{}{}", prefix, entry.pat.as_str()))?;
+            conflicts.extend(self.conflicts_with(&pat, &router.route(index).methods));
+            entries.push(Entry{pat, at: Some(self.sub.len()), index});
+        }
+        self.record(conflicts)?;
+        for entry in entries {
+            self.tree.insert(&entry.pat, self.entries.len());
+            self.entries.push(entry);
+        }
+        self.sub.push(Mount{prefix: prefix.to_string(), router});
+        Ok(self)
+    }
+
+    /// Whether a route here or in a mounted router is named `name`.
+    fn has_name(&self, name: &str) -> bool {
+        self.entry_routes().any(|(_, r)| r.name.as_deref() == Some(name))
+    }
+
+    /// The conflicts of a new route `pat` accepting `methods` with those already here.
+    fn conflicts_with(&self, pat: &Pattern, methods: &MethodSet) -> Vec<RouteConflict> {
+        let mut conflicts = Vec::new();
+        for (e, entry) in self.entries.iter().enumerate() {
+            let common = self.route(e).methods.intersection(methods);
+            if common.is_empty() {
+                continue;
+            }
+            if let Some(kind) = conflict_between(&entry.pat, pat) {
+                conflicts.push(RouteConflict {
+                    kind,
+                    route: pat.as_str().to_string(),
+                    other: entry.pat.as_str().to_string(),
+                    methods: common,
+                });
+            }
+        }
+        conflicts
+    }
+
+    /// Records `conflicts`, or refuses the first one in strict mode.
+    fn record(&mut self, conflicts: Vec<RouteConflict>) -> Result<(), RegisterError> {
+        if self.strict {
+            if let Some(conflict) = conflicts.into_iter().next() {
+                return Err(RegisterError::Conflict(conflict));
+            }
+        } else {
+            self.conflicts.extend(conflicts);
+        }
+        Ok(())
+    }
+
+    /// Puts `middlewares` in front of those of every route, including mounted ones.
+    pub fn wrap(&mut self, middlewares: &[Arc<dyn Middleware>]) {
+        if middlewares.is_empty() {
+            return;
+        }
+        for route in &mut self.routes {
+            route.middlewares.splice(0..0, middlewares.iter().cloned());
+        }
+        for mount in &mut self.sub {
+            mount.router.wrap(middlewares);
+        }
     }
 
-    pub fn register(&mut self, pattern: &str, handle: Arc<dyn EndPoint>, methods: MethodSet) -> Option<&mut Self> {
-        self.routes.push(Route{pat: Pattern::from_str(pattern)?, methods, next: handle});
-        Some(self)
+    pub fn register(&mut self, pattern: &str, handle: Arc<dyn EndPoint>, methods: MethodSet) -> Result<&mut Self, RegisterError> {
+        self.register_with(pattern, handle, methods, Vec::new())
+    }
+
+    /// Registers a route whose handler is wrapped by `middlewares`, the first one outermost.
+    ///
+    /// The route is checked against the earlier ones accepting any of the
+    /// same methods; conflicts are recorded, or refused in strict mode.
+    pub fn register_with(&mut self, pattern: &str, handle: Arc<dyn EndPoint>, methods: MethodSet, middlewares: Vec<Arc<dyn Middleware>>) -> Result<&mut Self, RegisterError> {
+        self.add(None, pattern, handle, methods, middlewares)
+    }
+
+    /// Registers a route that [`Self::url_for`] can build urls for under `name`.
+    pub fn register_named(&mut self, name: &str, pattern: &str, handle: Arc<dyn EndPoint>, methods: MethodSet, middlewares: Vec<Arc<dyn Middleware>>) -> Result<&mut Self, RegisterError> {
+        if self.has_name(name) {
+            return Err(RegisterError::DuplicateName(name.to_string()));
+        }
+        self.add(Some(name.to_string()), pattern, handle, methods, middlewares)
+    }
+
+    fn add(&mut self, name: Option<String>, pattern: &str, handle: Arc<dyn EndPoint>, methods: MethodSet, middlewares: Vec<Arc<dyn Middleware>>) -> Result<&mut Self, RegisterError> {
+        let pat = Pattern::from_str(pattern)?;
+        let conflicts = self.conflicts_with(&pat, &methods);
+        self.record(conflicts)?;
+        self.tree.insert(&pat, self.entries.len());
+        self.entries.push(Entry{pat: pat.clone(), at: None, index: self.routes.len()});
+        self.routes.push(Route{name, pat, methods, middlewares, next: handle});
+        Ok(self)
     }
     
 }
 
+/// The named routes of a router, with the patterns they were registered with.
+///
+/// Handlers find it among the request extensions as `Arc<Urls>`, to build
+/// links with `url_for` instead of spelling them out.
+#[derive(Clone, Default)]
+pub struct Urls {
+    routes: HashMap<String, Pattern>,
+}
+
+impl Urls {
+
+    /// Same as [`Router::url_for`].
+    pub fn url_for(&self, name: &str, params: &[(&str, &str)]) -> Result<String, UrlError> {
+        let pat = self.routes.get(name).ok_or_else(|| UrlError::UnknownRoute(name.to_string()))?;
+        pat.build_url(params)
+    }
+
+}
+
+fn options_response(allowed: MethodSet) -> HttpResponse {
+    let mut resp = HttpResponse::create_200_ok();
+    resp.headers.insert("Allow".to_string(), allowed.to_allow());
+    resp
+}
+
+/// Routing as an endpoint. Unmatched requests fail with
+/// [`HandleError::NotFound`] or [`HandleError::MethodNotAllowed`], which the
+/// processor turns into a 404 or 405 unless an error mapper has a response.
+///
+/// `OPTIONS` requests no route handles are answered with the methods of the
+/// matching routes, or of the whole router for `OPTIONS *`.
+#[async_trait]
+impl EndPoint for Router {
+    async fn handle<'a, 'b>(&self, req: &'a mut HttpRequest<'b>, _captures: Vec<&'b str>) -> HttpResult {
+        let header = req.header;
+        match self.routing(req).await {
+            Ok(resp) => Ok(resp),
+            Err(RouteError::MethodNotAllowed(allowed)) if header.method == Method::OPTIONS => Ok(options_response(allowed)),
+            Err(RouteError::NotFound) if header.method == Method::OPTIONS && header.url == "*" => Ok(options_response(self.all_methods())),
+            Err(RouteError::MethodNotAllowed(allowed)) => Err(HandleError::MethodNotAllowed(allowed)),
+            Err(RouteError::NotFound) => Err(HandleError::NotFound),
+            Err(RouteError::HandleError(e)) => Err(e),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::http::{HttpRequestHeader, ReqError, ResponseBody};
+    use crate::app::ConflictKind;
+    use super::*;
+
+    struct Named(&'static str);
+
+    #[async_trait]
+    impl EndPoint for Named {
+        async fn handle<'a, 'b>(&self, _req: &'a mut HttpRequest<'b>, captures: Vec<&'b str>) -> HttpResult {
+            Ok(HttpResponse {
+                body: ResponseBody::from(format!("{}{:?}", self.0, captures)),
+                ..HttpResponse::create_200_ok()
+            })
+        }
+    }
+
+    fn methods(ms: &[Method]) -> MethodSet {
+        let mut set = MethodSet::new();
+        for m in ms {
+            set.insert(*m);
+        }
+        set
+    }
+
+    async fn route(router: &Router, method: Method, url: &str) -> Result<String, RouteError> {
+        let mut header = HttpRequestHeader::new();
+        header.method = method;
+        header.url = url.to_string();
+        let mut rx: &[u8] = b"";
+        let mut req = HttpRequest::new(&header, &mut rx);
+        let resp = router.routing(&mut req).await?;
+        match resp.body {
+            ResponseBody::Bytes(b) => Ok(String::from_utf8(b).unwrap()),
+            _ => unreachable!(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_mount() {
+        let mut admin = Router::new();
+        admin.register("", Arc::new(Named("admin")), methods(&[Method::GET])).unwrap();
+        admin.register("/users/{}", Arc::new(Named("user")), methods(&[Method::GET])).unwrap();
+        admin.register("/users", Arc::new(Named("create")), methods(&[Method::POST])).unwrap();
+        let mut router = Router::new();
+        router.mount("/admin/", admin).unwrap();
+        router.register("/admin/{path}", Arc::new(Named("fallback")), methods(&[Method::GET])).unwrap();
+
+        assert_eq!(route(&router, Method::GET, "/admin").await.unwrap(), "admin[]");
+        assert_eq!(route(&router, Method::GET, "/admin/users/7").await.unwrap(), "user[\"7\"]");
+        assert_eq!(route(&router, Method::GET, "/admin/other").await.unwrap(), "fallback[\"other\"]");
+        assert!(matches!(route(&router, Method::GET, "/administrator").await, Err(RouteError::NotFound)));
+        assert!(matches!(route(&router, Method::DELETE, "/admin/users").await, Err(RouteError::MethodNotAllowed(_))));
+    }
+
+    #[tokio::test]
+    async fn test_mount_specificity() {
+        let mut api = Router::new();
+        api.register("/{path}", Arc::new(Named("proxy")), methods(&[Method::GET])).unwrap();
+        let mut router = Router::new();
+        router.mount("/api", api).unwrap();
+        router.register("/api/users", Arc::new(Named("users")), methods(&[Method::GET])).unwrap();
+
+        assert_eq!(route(&router, Method::GET, "/api/users").await.unwrap(), "users[]");
+        assert_eq!(route(&router, Method::GET, "/api/posts").await.unwrap(), "proxy[\"posts\"]");
+        assert_eq!(router.find("/api/users").unwrap().0.pat.as_str(), "/api/users");
+        assert!(router.conflicts().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_extension_methods() {
+        let propfind = Method::from_str("PROPFIND").unwrap();
+        let mkcol = Method::from_str("MKCOL").unwrap();
+        let mut router = Router::new();
+        router.register("/dav/{path}", Arc::new(Named("propfind")), methods(&[propfind, Method::CONNECT])).unwrap();
+        router.register("/dav/{path}", Arc::new(Named("get")), methods(&[Method::GET])).unwrap();
+
+        assert_eq!(route(&router, propfind, "/dav/a").await.unwrap(), "propfind[\"a\"]");
+        assert_eq!(route(&router, Method::CONNECT, "/dav/a").await.unwrap(), "propfind[\"a\"]");
+        match route(&router, mkcol, "/dav/a").await {
+            Err(RouteError::MethodNotAllowed(allowed)) => assert_eq!(allowed.to_allow(), "GET, HEAD, OPTIONS, CONNECT, PROPFIND"),
+            _ => panic!("expected 405"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_method_fall_through() {
+        let mut router = Router::new();
+        router.register("/x", Arc::new(Named("get")), methods(&[Method::GET])).unwrap();
+        router.register("/x", Arc::new(Named("post")), methods(&[Method::POST])).unwrap();
+        router.register("/{}", Arc::new(Named("put")), methods(&[Method::PUT, Method::GET])).unwrap();
+
+        assert_eq!(route(&router, Method::GET, "/x").await.unwrap(), "get[]");
+        assert_eq!(route(&router, Method::POST, "/x").await.unwrap(), "post[]");
+        assert_eq!(route(&router, Method::PUT, "/x").await.unwrap(), "put[\"x\"]");
+        match route(&router, Method::DELETE, "/x").await {
+            Err(RouteError::MethodNotAllowed(allowed)) => assert_eq!(allowed.to_allow(), "GET, POST, PUT, HEAD, OPTIONS"),
+            _ => panic!("expected 405"),
+        }
+        match route(&router, Method::POST, "/y").await {
+            Err(RouteError::MethodNotAllowed(allowed)) => assert_eq!(allowed.to_allow(), "GET, PUT, HEAD, OPTIONS"),
+            _ => panic!("expected 405"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_head_and_options() {
+        let mut router = Router::new();
+        router.register("/x", Arc::new(Named("get")), methods(&[Method::GET])).unwrap();
+        router.register("/x", Arc::new(Named("head")), methods(&[Method::HEAD])).unwrap();
+        router.register("/y", Arc::new(Named("get")), methods(&[Method::GET])).unwrap();
+        router.register("/z", Arc::new(Named("options")), methods(&[Method::OPTIONS, Method::POST])).unwrap();
+
+        assert_eq!(route(&router, Method::HEAD, "/x").await.unwrap(), "head[]");
+        assert_eq!(route(&router, Method::HEAD, "/y").await.unwrap(), "get[]");
+        assert_eq!(route(&router, Method::OPTIONS, "/z").await.unwrap(), "options[]");
+
+        let allow = |url: &'static str| {
+            let router = &router;
+            async move {
+                let mut header = HttpRequestHeader::new();
+                header.method = Method::OPTIONS;
+                header.url = url.to_string();
+                let mut rx: &[u8] = b"";
+                let mut req = HttpRequest::new(&header, &mut rx);
+                let resp = router.handle(&mut req, Vec::new()).await;
+                resp.ok()?.headers.get("Allow").map(str::to_string)
+            }
+        };
+        assert_eq!(allow("/x").await.as_deref(), Some("GET, HEAD, OPTIONS"));
+        assert_eq!(allow("*").await.as_deref(), Some("GET, POST, HEAD, OPTIONS"));
+        assert_eq!(allow("/nowhere").await, None);
+    }
+
+    #[tokio::test]
+    async fn test_not_found_with_accepting_route() {
+        let root = std::env::temp_dir().join(format!("router_not_found_test_{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        let mut files = Router::new();
+        files.register("/{path}", Arc::new(crate::app::StaticDir::new(&root).unwrap()), methods(&[Method::GET])).unwrap();
+        let mut router = Router::new();
+        router.mount("/static", files).unwrap();
 
+        assert!(matches!(route(&router, Method::GET, "/static/missing.css").await, Err(RouteError::NotFound)));
+        assert!(matches!(route(&router, Method::HEAD, "/static/missing.css").await, Err(RouteError::NotFound)));
+        match route(&router, Method::DELETE, "/static/missing.css").await {
+            Err(RouteError::MethodNotAllowed(allowed)) => assert_eq!(allowed.to_allow(), "GET, HEAD, OPTIONS"),
+            _ => panic!("expected 405"),
+        }
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    struct Typed;
+
+    #[async_trait]
+    impl EndPoint for Typed {
+        async fn handle<'a, 'b>(&self, req: &'a mut HttpRequest<'b>, _captures: Vec<&'b str>) -> HttpResult {
+            let id: u32 = req.param_as("id")?;
+            Ok(HttpResponse {
+                body: ResponseBody::from(format!("{} {}", id, req.param("rest").unwrap_or_default())),
+                ..HttpResponse::create_200_ok()
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_named_params() {
+        let mut router = Router::new();
+        router.register("/users/{id}/files/{rest:path}", Arc::new(Typed), methods(&[Method::GET])).unwrap();
+
+        assert_eq!(route(&router, Method::GET, "/users/7/files/a/b.txt").await.unwrap(), "7 a/b.txt");
+        assert!(matches!(route(&router, Method::GET, "/users/x/files/a").await,
+            Err(RouteError::HandleError(HandleError::ReqError(ReqError::BadParam(_))))));
+
+        let mut header = HttpRequestHeader::new();
+        header.url = "/users/x/files/a".to_string();
+        let mut rx: &[u8] = b"";
+        let mut req = HttpRequest::new(&header, &mut rx);
+        assert!(matches!(router.handle(&mut req, Vec::new()).await, Err(HandleError::ReqError(ReqError::BadParam(_)))));
+    }
+
+    #[tokio::test]
+    async fn test_query_routes() {
+        let mut router = Router::new();
+        router.register("/search?q={}&page={?}", Arc::new(Named("query")), methods(&[Method::GET])).unwrap();
+        router.register("/search", Arc::new(Named("form")), methods(&[Method::GET])).unwrap();
+
+        assert_eq!(route(&router, Method::GET, "/search?page=2&q=rust+web").await.unwrap(), "query[]");
+        assert_eq!(route(&router, Method::GET, "/search?page=2").await.unwrap(), "form[]");
+        assert_eq!(route(&router, Method::GET, "/search").await.unwrap(), "form[]");
+        assert!(router.find("/search?q=x").is_some_and(|(r, _)| r.pat.match_query(&Query::parse("q=x"))));
+    }
+
+    #[tokio::test]
+    async fn test_constrained_routes() {
+        let mut router = Router::new();
+        router.register("/users/{id:int}", Arc::new(Named("by_id")), methods(&[Method::GET])).unwrap();
+        router.register("/users/{name}", Arc::new(Named("by_name")), methods(&[Method::GET])).unwrap();
+
+        assert_eq!(route(&router, Method::GET, "/users/42").await.unwrap(), "by_id[\"42\"]");
+        assert_eq!(route(&router, Method::GET, "/users/bob").await.unwrap(), "by_name[\"bob\"]");
+        assert!(router.register("/users/{id:[}", Arc::new(Named("bad")), methods(&[Method::GET])).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_specificity() {
+        let mut router = Router::new();
+        router.register("/{all}", Arc::new(Named("all")), methods(&[Method::GET])).unwrap();
+        router.register("/files/{path}", Arc::new(Named("path")), methods(&[Method::GET])).unwrap();
+        router.register("/files/{}", Arc::new(Named("dir")), methods(&[Method::GET])).unwrap();
+        router.register("/files/readme", Arc::new(Named("static")), methods(&[Method::GET])).unwrap();
+
+        assert_eq!(route(&router, Method::GET, "/files/readme").await.unwrap(), "static[]");
+        assert_eq!(route(&router, Method::GET, "/files/a").await.unwrap(), "dir[\"a\"]");
+        assert_eq!(route(&router, Method::GET, "/files/a/b").await.unwrap(), "path[\"a/b\"]");
+        assert_eq!(route(&router, Method::GET, "/other").await.unwrap(), "all[\"other\"]");
+        assert!(router.conflicts().is_empty());
+    }
+
+    #[test]
+    fn test_conflicts() {
+        let mut router = Router::new();
+        router.register("/users/{id}", Arc::new(Named("a")), methods(&[Method::GET, Method::POST])).unwrap();
+        router.register("/users/{name}", Arc::new(Named("b")), methods(&[Method::GET])).unwrap();
+        router.register("/users/{other}", Arc::new(Named("c")), methods(&[Method::DELETE])).unwrap();
+        let conflicts = router.conflicts();
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].kind, ConflictKind::Unreachable);
+        assert_eq!(conflicts[0].route, "/users/{name}");
+        assert_eq!(conflicts[0].methods, methods(&[Method::GET]));
+
+        let mut strict = Router::new();
+        strict.set_strict(true).unwrap();
+        strict.register("/{id:int}", Arc::new(Named("a")), methods(&[Method::GET])).unwrap();
+        assert!(matches!(strict.register("/{id:[0-9]+}", Arc::new(Named("b")), methods(&[Method::GET])),
+            Err(RegisterError::Conflict(RouteConflict{kind: ConflictKind::Ambiguous, ..}))));
+        assert_eq!(strict.routes.len(), 1);
+    }
+
+    #[test]
+    fn test_mount_conflicts() {
+        let admin = || {
+            let mut admin = Router::new();
+            admin.register("/users/{id}", Arc::new(Named("a")), methods(&[Method::GET])).unwrap();
+            admin
+        };
+        let mut router = Router::new();
+        router.register("/admin/users/{name}", Arc::new(Named("b")), methods(&[Method::GET])).unwrap();
+        router.mount("/admin", admin()).unwrap();
+        let conflicts = router.conflicts();
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].route, "/admin/users/{id}");
+        assert_eq!(conflicts[0].other, "/admin/users/{name}");
+        assert!(matches!(router.set_strict(true), Err(RegisterError::Conflict(_))));
+
+        let mut inner = admin();
+        inner.register("/users/{name}", Arc::new(Named("c")), methods(&[Method::GET])).unwrap();
+        let mut outer = Router::new();
+        outer.mount("/v1", inner).unwrap();
+        assert_eq!(outer.conflicts()[0].route, "/v1/users/{name}");
+
+        let mut strict = Router::new();
+        strict.set_strict(true).unwrap();
+        strict.register("/admin/users/{name}", Arc::new(Named("b")), methods(&[Method::GET])).unwrap();
+        assert!(matches!(strict.mount("/admin", admin()), Err(RegisterError::Conflict(_))));
+        let mut inner = admin();
+        inner.register("/users/{name}", Arc::new(Named("c")), methods(&[Method::GET])).unwrap();
+        assert!(matches!(strict.mount("/v1", inner), Err(RegisterError::Conflict(_))));
+        assert!(strict.sub.is_empty());
+    }
+
+    #[test]
+    fn test_url_for() {
+        let mut admin = Router::new();
+        admin.register_named("user.show", "/users/{id:int}", Arc::new(Named("user")), methods(&[Method::GET]), Vec::new()).unwrap();
+        let mut router = Router::new();
+        router.register_named("search", "/search?q={}", Arc::new(Named("search")), methods(&[Method::GET]), Vec::new()).unwrap();
+        router.mount("/admin", admin).unwrap();
+
+        assert_eq!(router.url_for("user.show", &[("id", "7")]).unwrap(), "/admin/users/7");
+        assert_eq!(router.url_for("search", &[("q", "a&b c")]).unwrap(), "/search?q=a%26b%20c");
+        assert_eq!(router.url_for("user.show", &[]), Err(UrlError::MissingParam("id".to_string())));
+        assert_eq!(router.url_for("user.edit", &[]), Err(UrlError::UnknownRoute("user.edit".to_string())));
+        assert_eq!(router.urls().url_for("user.show", &[("id", "7")]).unwrap(), "/admin/users/7");
+        assert!(matches!(router.register_named("search", "/find", Arc::new(Named("find")), methods(&[Method::GET]), Vec::new()),
+            Err(RegisterError::DuplicateName(_))));
+        assert!(matches!(router.register_named("user.show", "/u/{}", Arc::new(Named("user")), methods(&[Method::GET]), Vec::new()),
+            Err(RegisterError::DuplicateName(_))));
+        let mut other = Router::new();
+        other.register_named("search", "/search", Arc::new(Named("search")), methods(&[Method::GET]), Vec::new()).unwrap();
+        assert!(matches!(router.mount("/other", other), Err(RegisterError::DuplicateName(name)) if name == "search"));
+    }
+}
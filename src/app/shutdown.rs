@@ -0,0 +1,63 @@
+use std::sync::Arc;
+
+use tokio::sync::watch;
+
+/// Stops a running [`Application`](super::Application): accepting ends,
+/// requests in flight are answered with `Connection: close` and idle
+/// connections are closed. Clones stop the same application.
+#[derive(Debug, Clone)]
+pub struct ShutdownHandle {
+    tx: Arc<watch::Sender<bool>>,
+}
+
+impl Default for ShutdownHandle {
+    fn default() -> Self {
+        ShutdownHandle::new()
+    }
+}
+
+impl ShutdownHandle {
+
+    pub fn new() -> Self {
+        ShutdownHandle {
+            tx: Arc::new(watch::Sender::new(false)),
+        }
+    }
+
+    pub fn shutdown(&self) {
+        self.tx.send_replace(true);
+    }
+
+    pub fn is_shutting_down(&self) -> bool {
+        *self.tx.borrow()
+    }
+
+    /// Resolves once [`Self::shutdown`] has been called.
+    pub async fn wait(&self) {
+        let mut rx = self.tx.subscribe();
+        // The sender lives as long as `self`, so this cannot fail.
+        let _ = rx.wait_for(|stopping| *stopping).await;
+    }
+
+}
+
+/// Resolves on SIGINT, or on SIGTERM where there is one.
+pub(crate) async fn wait_for_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut term) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {},
+                    _ = term.recv() => {},
+                }
+            },
+            Err(_) => { let _ = tokio::signal::ctrl_c().await; },
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+    }
+}
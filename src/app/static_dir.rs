@@ -0,0 +1,142 @@
+use std::{io, path::{Path, PathBuf}, sync::Arc};
+
+use async_trait::async_trait;
+
+use crate::http::{percent_decode, HttpRequest, HttpResponse, Method};
+use super::{EndPoint, HandleError, HttpResult, MethodSet, RegisterError, Router};
+
+/// Serves the files under a root directory.
+///
+/// The last capture of the route (normally a `{path}`) is taken as the path
+/// relative to the root, and a route without captures serves the root itself.
+/// [`Router::serve_dir`] registers the routes needed under a prefix.
+/// Directories are answered with their `index.html`, once their url ends
+/// with `/` so that relative links in it resolve; before that with a 308.
+pub struct StaticDir {
+    root: PathBuf,
+    index: &'static str,
+}
+
+impl StaticDir {
+
+    pub fn new(root: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(StaticDir {
+            root: std::fs::canonicalize(root)?,
+            index: "index.html",
+        })
+    }
+
+    /// Turns the captured url path into a path under the root, refusing
+    /// anything that could climb out of it.
+    fn relative_path(capture: &str) -> Option<PathBuf> {
+        let decoded = percent_decode(capture)?;
+        let mut rel = PathBuf::new();
+        for seg in decoded.split('/') {
+            match seg {
+                "" | "." => {},
+                ".." => return None,
+                seg if seg.contains(['\\', '\0']) => return None,
+                seg => rel.push(seg),
+            }
+        }
+        Some(rel)
+    }
+
+    /// Resolves symlinks and checks the result is still inside the root.
+    async fn resolve(&self, path: &Path) -> Result<PathBuf, HandleError> {
+        let real = match tokio::fs::canonicalize(path).await {
+            Ok(real) => real,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Err(HandleError::NotFound),
+            Err(e) => return Err(HandleError::IoError(e)),
+        };
+        if !real.starts_with(&self.root) {
+            return Err(HandleError::NotFound);
+        }
+        Ok(real)
+    }
+
+}
+
+#[async_trait]
+impl EndPoint for StaticDir {
+    async fn handle<'a, 'b>(&self, req: &'a mut HttpRequest<'b>, captures: Vec<&'b str>) -> HttpResult {
+        let rel = match Self::relative_path(captures.last().copied().unwrap_or("")) {
+            Some(rel) => rel,
+            None => return Ok(HttpResponse::create_403_forbidden()),
+        };
+        let mut path = self.resolve(&self.root.join(rel)).await?;
+        if tokio::fs::metadata(&path).await?.is_dir() {
+            if !req.header.path().ends_with('/') {
+                let query = req.header.query_str().map(|q| format!("?{}", q)).unwrap_or_default();
+                let mut resp = HttpResponse::create_308_permanent_redirect();
+                resp.headers.insert("Location", format!("{}/{}", req.header.path(), query));
+                return Ok(resp);
+            }
+            path = self.resolve(&path.join(self.index)).await?;
+        }
+        match HttpResponse::serve_file(&path, req.header).await {
+            Ok(resp) => Ok(resp),
+            Err(e) if e.kind() == io::ErrorKind::NotFound || e.kind() == io::ErrorKind::InvalidInput => Err(HandleError::NotFound),
+            Err(e) => Err(HandleError::IoError(e)),
+        }
+    }
+}
+
+impl Router {
+
+    /// Serves `dir` on `GET` under `prefix`: `{prefix}/{path}` for the files,
+    /// plus `{prefix}/` and `{prefix}` for the root, as a capture cannot be empty.
+    pub fn serve_dir(&mut self, prefix: &str, dir: StaticDir) -> Result<&mut Self, RegisterError> {
+        let prefix = prefix.trim_end_matches('/');
+        let dir: Arc<dyn EndPoint> = Arc::new(dir);
+        let mut methods = MethodSet::new();
+        methods.insert(Method::GET);
+        if !prefix.is_empty() {
+            self.register(prefix, dir.clone(), methods.clone())?;
+        }
+        self.register(&format!("{}/", prefix), dir.clone(), methods.clone())?;
+        self.register(&format!("{}/{{path}}", prefix), dir, methods)
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn get(router: &Router, url: &str) -> (u32, Option<String>) {
+        let mut header = crate::http::HttpRequestHeader::new();
+        header.url = url.to_string();
+        let mut rx: &[u8] = b"";
+        let mut req = HttpRequest::new(&header, &mut rx);
+        let resp = router.handle(&mut req, Vec::new()).await.unwrap();
+        (resp.status_code, resp.headers.get("Location").map(str::to_string))
+    }
+
+    #[tokio::test]
+    async fn test_serve_dir() {
+        let root = std::env::temp_dir().join(format!("static_dir_test_{}", std::process::id()));
+        std::fs::create_dir_all(root.join("docs")).unwrap();
+        std::fs::write(root.join("index.html"), "root").unwrap();
+        std::fs::write(root.join("docs/index.html"), "docs").unwrap();
+        let mut router = Router::new();
+        router.serve_dir("/static/", StaticDir::new(&root).unwrap()).unwrap();
+
+        assert_eq!(get(&router, "/static/").await, (200, None));
+        assert_eq!(get(&router, "/static").await, (308, Some("/static/".to_string())));
+        assert_eq!(get(&router, "/static/docs?v=1").await, (308, Some("/static/docs/?v=1".to_string())));
+        assert_eq!(get(&router, "/static/docs/").await, (200, None));
+        assert_eq!(get(&router, "/static/docs/index.html").await, (200, None));
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_relative_path() {
+        assert_eq!(StaticDir::relative_path("css/./site.css"), Some(PathBuf::from("css/site.css")));
+        assert_eq!(StaticDir::relative_path("a%20b.html"), Some(PathBuf::from("a b.html")));
+        assert_eq!(StaticDir::relative_path(""), Some(PathBuf::new()));
+        assert_eq!(StaticDir::relative_path("../etc/passwd"), None);
+        assert_eq!(StaticDir::relative_path("a/%2e%2e/%2e%2e/etc"), None);
+        assert_eq!(StaticDir::relative_path("a%5c..%5cb"), None);
+    }
+}
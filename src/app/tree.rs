@@ -0,0 +1,162 @@
+use super::Pattern;
+
+#[derive(Default)]
+struct Node {
+    /// Radix-compressed literal edges; no two labels share their first byte.
+    literals: Vec<(Vec<u8>, Node)>,
+    /// Routes without placeholders whose pattern ends at this node, by registration index.
+    routes: Vec<usize>,
+    /// Routes whose first placeholder comes right after the literal text up to this node.
+    holders: Vec<usize>,
+}
+
+impl Node {
+
+    fn insert(&mut self, lit: &[u8], route: usize, holders: bool) {
+        if lit.is_empty() {
+            if holders {
+                self.holders.push(route);
+            } else {
+                self.routes.push(route);
+            }
+            return;
+        }
+        let found = self.literals.iter().position(|(label, _)| label[0] == lit[0]);
+        let i = match found {
+            Some(i) => i,
+            None => {
+                self.literals.push((lit.to_vec(), Node::default()));
+                return self.literals.last_mut().unwrap().1.insert(&[], route, holders);
+            },
+        };
+        let (label, child) = &mut self.literals[i];
+        let common = label.iter().zip(lit).take_while(|(a, b)| a == b).count();
+        if common < label.len() {
+            // Split the edge at the end of the common prefix.
+            let tail = label.split_off(common);
+            let old = std::mem::take(child);
+            child.literals.push((tail, old));
+        }
+        child.insert(&lit[common..], route, holders);
+    }
+
+}
+
+/// Prefix tree over the literal text the patterns start with, so that finding
+/// the routes of a url costs in the length of the url rather than the number
+/// of routes. Only the routes whose leading text the url starts with are then
+/// matched against it, each once, in the way [`Pattern::match_url`] does.
+#[derive(Default)]
+pub(crate) struct RouteTree {
+    root: Node,
+}
+
+impl RouteTree {
+
+    pub(crate) fn insert(&mut self, pat: &Pattern, route: usize) {
+        let lead = pat.holders.first().map_or(pat.url.len(), |h| h.pos);
+        self.root.insert(&pat.url.as_bytes()[..lead], route, !pat.holders.is_empty());
+    }
+
+    /// Every route matching the path `url` with its captures, in registration
+    /// order; `pattern` gives the pattern of a route by its index.
+    pub(crate) fn lookup<'u, 'p>(&self, url: &'u str, pattern: impl Fn(usize) -> &'p Pattern) -> Vec<(usize, Vec<&'u str>)> {
+        let bytes = url.as_bytes();
+        let mut candidates = Vec::new();
+        let mut node = &self.root;
+        let mut j = 0;
+        loop {
+            candidates.extend_from_slice(&node.holders);
+            if j == bytes.len() {
+                candidates.extend_from_slice(&node.routes);
+                break;
+            }
+            match node.literals.iter().find(|(label, _)| bytes[j..].starts_with(label)) {
+                Some((label, child)) => {
+                    j += label.len();
+                    node = child;
+                },
+                None => break,
+            }
+        }
+        candidates.sort_unstable();
+        candidates.into_iter()
+            .filter_map(|r| pattern(r).match_path(url).map(|captures| (r, captures)))
+            .collect()
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tree(pats: &[&str]) -> (RouteTree, Vec<Pattern>) {
+        let pats: Vec<_> = pats.iter().map(|p| Pattern::from_str(p).unwrap()).collect();
+        let mut tree = RouteTree::default();
+        for (i, p) in pats.iter().enumerate() {
+            tree.insert(p, i);
+        }
+        (tree, pats)
+    }
+
+    #[test]
+    fn test_tree_same_as_pattern() {
+        let pats = [
+            "/hello/{}/{d}/{dir}",
+            "/hello/{}",
+            "/hello/{d}/{path}",
+            "/help/{}/{path}",
+            "/hello",
+            "/help/{}",
+            "/{all}",
+            "/{path}/{path}",
+            "/{path}/x/{path}/y/{path}",
+            "/files/{}.{}",
+            "/files/{}/{all}",
+            "/{}/{}/{path}",
+        ];
+        let urls = [
+            "/hello/world/from/rust",
+            "/hello/world/from",
+            "/hello/world/from/evil/rust",
+            "/help/me/from/evil",
+            "/hello",
+            "/hell0",
+            "/help/me",
+            "/help/",
+            "/",
+            "/a/b/c/d",
+            "/a/x/b/y/c",
+            "/a/x/b/x/c/y/d/y/e",
+            "/files/a.tar.gz",
+            "/files/a/b/c",
+            "/files/",
+        ];
+        let (tree, pats) = tree(&pats);
+        for url in urls {
+            let linear: Vec<_> = pats.iter().enumerate()
+                .filter_map(|(i, p)| p.match_url(url).map(|c| (i, c)))
+                .collect();
+            assert_eq!(tree.lookup(url, |i| &pats[i]), linear, "{}", url);
+        }
+    }
+
+    #[test]
+    fn test_tree_split_edges() {
+        let (tree, pats) = tree(&["/users", "/user/{}", "/use", "/users/{}/posts"]);
+        let lookup = |url| tree.lookup(url, |i| &pats[i]);
+        assert_eq!(lookup("/users"), vec![(0, vec![])]);
+        assert_eq!(lookup("/user/7"), vec![(1, vec!["7"])]);
+        assert_eq!(lookup("/use"), vec![(2, vec![])]);
+        assert_eq!(lookup("/users/7/posts"), vec![(3, vec!["7"])]);
+        assert_eq!(lookup("/us"), vec![]);
+    }
+
+    #[test]
+    fn test_tree_one_match_per_route() {
+        let (tree, pats) = tree(&["/{path}/x/{path}/y/{path}"]);
+        let url = format!("/{}", "a/x/b/y/".repeat(1000));
+        assert!(tree.lookup(&url, |i| &pats[i]).len() <= 1);
+    }
+}
@@ -0,0 +1,235 @@
+use std::{io, pin::Pin, task::{ready, Context, Poll}};
+
+use tokio::io::{AsyncBufRead, AsyncRead, ReadBuf};
+
+use super::{read_until_crlf_internal, BodyTooLarge, HeaderMap, RequestLimits};
+
+/// How the length of a request body is delimited on the wire.
+#[derive(Debug)]
+pub(crate) enum BodyKind {
+    /// `Content-Length` framing, holding the number of bytes still unread.
+    Length(u64),
+    /// `Transfer-Encoding: chunked` framing.
+    Chunked(ChunkedState),
+}
+
+#[derive(Debug)]
+enum ChunkedPhase {
+    Size,
+    Data(u64),
+    DataCrlf,
+    Trailers,
+    Done,
+}
+
+#[derive(Debug)]
+pub(crate) struct ChunkedState {
+    phase: ChunkedPhase,
+    line: Vec<u8>,
+    read: usize,
+    /// Bytes of data and trailers the body may still have.
+    allowed: u64,
+    /// Longest chunk size or trailer line, CRLF included.
+    max_line: usize,
+    /// Trailer fields the body may still have.
+    fields: usize,
+    pub(crate) trailers: HeaderMap,
+}
+
+impl ChunkedState {
+
+    pub(crate) fn new() -> Self {
+        let limits = RequestLimits::default();
+        ChunkedState {
+            phase: ChunkedPhase::Size,
+            line: Vec::new(),
+            read: 0,
+            allowed: u64::MAX,
+            max_line: limits.max_header_line + 2,
+            fields: limits.max_headers,
+            trailers: HeaderMap::new(),
+        }
+    }
+
+    /// Bounds the data and trailers by `max_body`, each line by
+    /// `max_header_line`, and the trailer fields by what `max_headers` leaves
+    /// after the `headers` fields of the request header.
+    pub(crate) fn set_limits(&mut self, limits: &RequestLimits, headers: usize) {
+        self.allowed = limits.max_body;
+        self.max_line = limits.max_header_line + 2;
+        self.fields = limits.max_headers.saturating_sub(headers);
+    }
+
+    pub(crate) fn is_done(&self) -> bool {
+        matches!(self.phase, ChunkedPhase::Done)
+    }
+
+}
+
+impl BodyKind {
+
+    pub(crate) fn is_done(&self) -> bool {
+        match self {
+            BodyKind::Length(remaining) => *remaining == 0,
+            BodyKind::Chunked(state) => state.is_done(),
+        }
+    }
+
+    /// Best guess of the bytes left, only used to size buffers.
+    pub(crate) fn size_hint(&self) -> u64 {
+        match self {
+            BodyKind::Length(remaining) => *remaining,
+            BodyKind::Chunked(_) => 0,
+        }
+    }
+
+}
+
+fn unexpected_eof() -> io::Error {
+    io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed before the request body was complete")
+}
+
+fn invalid_chunk(msg: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Whether `line` was cut short at `max` bytes.
+fn line_too_long(line: &[u8], max: usize) -> bool {
+    line.len() >= max && !line.ends_with(b"\r\n")
+}
+
+/// Fails when a chunk size line was cut short at `max` bytes.
+fn check_chunk_line(line: &[u8], max: usize) -> io::Result<()> {
+    if line_too_long(line, max) {
+        return Err(invalid_chunk("chunk line too long"));
+    }
+    Ok(())
+}
+
+/// Parses `chunk-size [ chunk-ext ] CRLF`, ignoring any extensions.
+fn parse_chunk_size(line: &[u8]) -> io::Result<u64> {
+    let line = line.strip_suffix(b"\r\n").ok_or_else(unexpected_eof)?;
+    let size = match line.iter().position(|&b| b == b';') {
+        Some(i) => &line[..i],
+        None => line,
+    };
+    let size = size.trim_ascii();
+    if size.is_empty() || size.len() > 16 || !size.iter().all(u8::is_ascii_hexdigit) {
+        return Err(invalid_chunk("invalid chunk size"));
+    }
+    let size = std::str::from_utf8(size).map_err(|_| invalid_chunk("invalid chunk size"))?;
+    u64::from_str_radix(size, 16).map_err(|_| invalid_chunk("invalid chunk size"))
+}
+
+/// `AsyncRead` view of a request body, decoding its framing.
+///
+/// The framing state is shared with the owning [`HttpRequest`](super::HttpRequest),
+/// so a body that is only partially read here can still be drained afterwards.
+pub struct BodyReader<'a> {
+    reader: &'a mut (dyn AsyncBufRead + Unpin + Send),
+    kind: &'a mut BodyKind,
+}
+
+impl<'a> BodyReader<'a> {
+
+    pub(crate) fn new(reader: &'a mut (dyn AsyncBufRead + Unpin + Send), kind: &'a mut BodyKind) -> Self {
+        BodyReader {
+            reader,
+            kind,
+        }
+    }
+
+    /// Returns `true` once the whole body, including any chunked trailers, has been read.
+    pub fn is_done(&self) -> bool {
+        self.kind.is_done()
+    }
+
+}
+
+impl AsyncBufRead for BodyReader<'_> {
+
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+        let me = self.get_mut();
+        let limit = match me.kind {
+            BodyKind::Length(remaining) => *remaining,
+            BodyKind::Chunked(state) => loop {
+                match state.phase {
+                    ChunkedPhase::Size => {
+                        ready!(read_until_crlf_internal(Pin::new(&mut *me.reader), cx, &mut state.line, &mut state.read, state.max_line))?;
+                        check_chunk_line(&state.line, state.max_line)?;
+                        let size = parse_chunk_size(&state.line)?;
+                        state.allowed = state.allowed.checked_sub(size).ok_or_else(BodyTooLarge::into_io)?;
+                        state.line.clear();
+                        state.phase = if size == 0 { ChunkedPhase::Trailers } else { ChunkedPhase::Data(size) };
+                    },
+                    ChunkedPhase::Data(size) => break size,
+                    ChunkedPhase::DataCrlf => {
+                        ready!(read_until_crlf_internal(Pin::new(&mut *me.reader), cx, &mut state.line, &mut state.read, state.max_line))?;
+                        check_chunk_line(&state.line, state.max_line)?;
+                        if state.line != b"\r\n" {
+                            return Poll::Ready(Err(invalid_chunk("missing CRLF after chunk data")));
+                        }
+                        state.line.clear();
+                        state.phase = ChunkedPhase::Size;
+                    },
+                    ChunkedPhase::Trailers => {
+                        // Trailers count as part of the body, so going over
+                        // a limit with them is a body too large.
+                        ready!(read_until_crlf_internal(Pin::new(&mut *me.reader), cx, &mut state.line, &mut state.read, state.max_line))?;
+                        if line_too_long(&state.line, state.max_line) {
+                            return Poll::Ready(Err(BodyTooLarge::into_io()));
+                        }
+                        state.allowed = state.allowed.checked_sub(state.line.len() as u64).ok_or_else(BodyTooLarge::into_io)?;
+                        let line = state.line.strip_suffix(b"\r\n").ok_or_else(unexpected_eof)?;
+                        if line.is_empty() {
+                            state.phase = ChunkedPhase::Done;
+                        } else {
+                            state.fields = state.fields.checked_sub(1).ok_or_else(BodyTooLarge::into_io)?;
+                            let line = std::str::from_utf8(line).map_err(|_| invalid_chunk("invalid trailer field"))?;
+                            let (k, v) = line.split_once(':').ok_or_else(|| invalid_chunk("invalid trailer field"))?;
+                            state.trailers.append(k, v.trim());
+                        }
+                        state.line.clear();
+                    },
+                    ChunkedPhase::Done => break 0,
+                }
+            },
+        };
+        if limit == 0 {
+            return Poll::Ready(Ok(&[]));
+        }
+        let available = ready!(Pin::new(&mut *me.reader).poll_fill_buf(cx))?;
+        if available.is_empty() {
+            return Poll::Ready(Err(unexpected_eof()));
+        }
+        let len = available.len().min(usize::try_from(limit).unwrap_or(usize::MAX));
+        Poll::Ready(Ok(&available[..len]))
+    }
+
+    fn consume(self: Pin<&mut Self>, amt: usize) {
+        let me = self.get_mut();
+        match me.kind {
+            BodyKind::Length(remaining) => *remaining -= amt as u64,
+            BodyKind::Chunked(state) => {
+                if let ChunkedPhase::Data(size) = state.phase {
+                    let size = size - amt as u64;
+                    state.phase = if size == 0 { ChunkedPhase::DataCrlf } else { ChunkedPhase::Data(size) };
+                }
+            },
+        }
+        Pin::new(&mut *me.reader).consume(amt);
+    }
+
+}
+
+impl AsyncRead for BodyReader<'_> {
+
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let available = ready!(self.as_mut().poll_fill_buf(cx))?;
+        let len = available.len().min(buf.remaining());
+        buf.put_slice(&available[..len]);
+        self.consume(len);
+        Poll::Ready(Ok(()))
+    }
+
+}
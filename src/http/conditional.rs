@@ -0,0 +1,145 @@
+use std::time::SystemTime;
+
+use httpdate::HttpDate;
+
+use super::{HttpRequestHeader, Method};
+
+/// Result of evaluating the conditional headers of a request.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Precondition {
+    Proceed,
+    /// Answer `304 Not Modified`.
+    NotModified,
+    /// Answer `412 Precondition Failed`.
+    Failed,
+}
+
+/// One entry of an `If-Match`/`If-None-Match` list, or an `ETag` value.
+struct EntityTag<'a> {
+    weak: bool,
+    opaque: &'a str,
+}
+
+impl<'a> EntityTag<'a> {
+
+    fn parse(s: &'a str) -> Option<(Self, &'a str)> {
+        let s = s.trim_start();
+        let (weak, s) = match s.strip_prefix("W/") {
+            Some(rest) => (true, rest),
+            None => (false, s),
+        };
+        let rest = s.strip_prefix('"')?;
+        let end = rest.find('"')?;
+        Some((EntityTag { weak, opaque: &rest[..end] }, &rest[end + 1..]))
+    }
+
+    fn strong_eq(&self, other: &EntityTag) -> bool {
+        !self.weak && !other.weak && self.opaque == other.opaque
+    }
+
+    fn weak_eq(&self, other: &EntityTag) -> bool {
+        self.opaque == other.opaque
+    }
+
+}
+
+/// Whether `list` (`*` or comma separated entity tags) matches `etag`.
+fn list_matches(list: &str, etag: Option<&str>, strong: bool) -> bool {
+    if list.trim() == "*" {
+        return true;
+    }
+    let etag = match etag.and_then(EntityTag::parse) {
+        Some((etag, _)) => etag,
+        None => return false,
+    };
+    let mut rest = list;
+    while let Some((tag, tail)) = EntityTag::parse(rest) {
+        if if strong { tag.strong_eq(&etag) } else { tag.weak_eq(&etag) } {
+            return true;
+        }
+        rest = tail.trim_start().strip_prefix(',').unwrap_or("");
+    }
+    false
+}
+
+fn parse_date(s: &str) -> Option<HttpDate> {
+    s.trim().parse().ok()
+}
+
+/// Evaluates `If-Match`, `If-Unmodified-Since`, `If-None-Match` and
+/// `If-Modified-Since` in the order of RFC 9110 13.2.2, against the current
+/// validators of the selected representation.
+///
+/// Handlers of unsafe methods should call this before making any change.
+pub fn evaluate_preconditions(req: &HttpRequestHeader, etag: Option<&str>, last_modified: Option<SystemTime>) -> Precondition {
+    let last_modified = last_modified.map(HttpDate::from);
+    if let Some(if_match) = req.get_para("If-Match") {
+        if !list_matches(if_match, etag, true) {
+            return Precondition::Failed;
+        }
+    } else if let (Some(since), Some(modified)) = (req.get_para("If-Unmodified-Since").and_then(parse_date), last_modified) {
+        if modified > since {
+            return Precondition::Failed;
+        }
+    }
+    let safe = matches!(req.method, Method::GET | Method::HEAD);
+    if let Some(if_none_match) = req.get_para("If-None-Match") {
+        if list_matches(if_none_match, etag, false) {
+            return if safe { Precondition::NotModified } else { Precondition::Failed };
+        }
+    } else if safe {
+        if let (Some(since), Some(modified)) = (req.get_para("If-Modified-Since").and_then(parse_date), last_modified) {
+            if modified <= since {
+                return Precondition::NotModified;
+            }
+        }
+    }
+    Precondition::Proceed
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn header(method: Method, paras: &[(&str, &str)]) -> HttpRequestHeader {
+        let mut req = HttpRequestHeader::new();
+        req.method = method;
+        for (k, v) in paras {
+            req.paras.append(*k, *v);
+        }
+        req
+    }
+
+    #[test]
+    fn test_etag_preconditions() {
+        let etag = Some("\"abc\"");
+        let req = header(Method::GET, &[("If-None-Match", "\"xyz\", W/\"abc\"")]);
+        assert_eq!(evaluate_preconditions(&req, etag, None), Precondition::NotModified);
+        let req = header(Method::GET, &[("If-None-Match", "\"xyz\"")]);
+        assert_eq!(evaluate_preconditions(&req, etag, None), Precondition::Proceed);
+        let req = header(Method::PUT, &[("If-None-Match", "*")]);
+        assert_eq!(evaluate_preconditions(&req, etag, None), Precondition::Failed);
+        let req = header(Method::PUT, &[("If-Match", "W/\"abc\"")]);
+        assert_eq!(evaluate_preconditions(&req, etag, None), Precondition::Failed);
+        let req = header(Method::PUT, &[("If-Match", "\"abc\"")]);
+        assert_eq!(evaluate_preconditions(&req, etag, None), Precondition::Proceed);
+    }
+
+    #[test]
+    fn test_date_preconditions() {
+        let modified = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+        let at = httpdate::fmt_http_date(modified);
+        let before = httpdate::fmt_http_date(modified - Duration::from_secs(60));
+        let req = header(Method::GET, &[("If-Modified-Since", &at)]);
+        assert_eq!(evaluate_preconditions(&req, None, Some(modified)), Precondition::NotModified);
+        let req = header(Method::GET, &[("If-Modified-Since", &before)]);
+        assert_eq!(evaluate_preconditions(&req, None, Some(modified)), Precondition::Proceed);
+        let req = header(Method::GET, &[("If-Unmodified-Since", &before)]);
+        assert_eq!(evaluate_preconditions(&req, None, Some(modified)), Precondition::Failed);
+        // If-None-Match takes precedence over If-Modified-Since.
+        let req = header(Method::GET, &[("If-None-Match", "\"other\""), ("If-Modified-Since", &at)]);
+        assert_eq!(evaluate_preconditions(&req, Some("\"abc\""), Some(modified)), Precondition::Proceed);
+    }
+}
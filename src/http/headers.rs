@@ -0,0 +1,211 @@
+/// Header fields in the order they were added, looked up ignoring the case
+/// of their names. A name may appear several times, e.g. `Set-Cookie`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HeaderMap {
+    entries: Vec<(String, String)>,
+}
+
+impl HeaderMap {
+
+    pub fn new() -> Self {
+        HeaderMap {
+            entries: Vec::new(),
+        }
+    }
+
+    /// The first value of `name`.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.entries.iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Every value of `name`, in order.
+    pub fn get_all<'h>(&'h self, name: &'h str) -> impl Iterator<Item = &'h str> {
+        self.entries.iter()
+            .filter(move |(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn contains_key(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    /// Sets `name` to the single value `value`: the first field of that name
+    /// keeps its place, the others are removed. Returns the first old value.
+    pub fn insert(&mut self, name: impl Into<String>, value: impl Into<String>) -> Option<String> {
+        let name = name.into();
+        let value = value.into();
+        match self.entries.iter().position(|(k, _)| k.eq_ignore_ascii_case(&name)) {
+            Some(i) => {
+                let old = std::mem::replace(&mut self.entries[i].1, value);
+                let mut j = 0_usize;
+                self.entries.retain(|(k, _)| {
+                    let keep = j <= i || !k.eq_ignore_ascii_case(&name);
+                    j += 1;
+                    keep
+                });
+                Some(old)
+            },
+            None => {
+                self.entries.push((name, value));
+                None
+            },
+        }
+    }
+
+    /// Adds a field, keeping those already there under the same name.
+    pub fn append(&mut self, name: impl Into<String>, value: impl Into<String>) {
+        self.entries.push((name.into(), value.into()));
+    }
+
+    /// Removes every field named `name`, returning the first value.
+    pub fn remove(&mut self, name: &str) -> Option<String> {
+        let old = self.get(name).map(str::to_string);
+        self.entries.retain(|(k, _)| !k.eq_ignore_ascii_case(name));
+        old
+    }
+
+    /// Keeps the fields for which `f` returns `true`.
+    pub fn retain(&mut self, mut f: impl FnMut(&str, &str) -> bool) {
+        self.entries.retain(|(k, v)| f(k, v));
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.entries.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// The elements of a comma separated list header, over all its fields,
+    /// trimmed and without empty ones (RFC 9110 5.6.1).
+    pub fn get_list<'h>(&'h self, name: &'h str) -> impl Iterator<Item = &'h str> {
+        self.get_all(name)
+            .flat_map(|v| v.split(','))
+            .map(str::trim)
+            .filter(|v| !v.is_empty())
+    }
+
+    /// `Content-Length`, if present and valid: digits only, so no sign.
+    /// Repeated identical values are accepted, as RFC 9112 6.3 allows;
+    /// differing ones are not.
+    pub fn content_length(&self) -> Option<u64> {
+        let mut len = None;
+        for v in self.get_list("Content-Length") {
+            if v.is_empty() || !v.bytes().all(|b| b.is_ascii_digit()) {
+                return None;
+            }
+            let v = v.parse::<u64>().ok()?;
+            if len.is_some_and(|len| len != v) {
+                return None;
+            }
+            len = Some(v);
+        }
+        len
+    }
+
+    /// The media type of `Content-Type`, without parameters such as `charset`.
+    pub fn content_type(&self) -> Option<&str> {
+        let v = self.get("Content-Type")?;
+        Some(v.split(';').next().unwrap_or(v).trim())
+    }
+
+    /// The options of the `Connection` header, e.g. `close` or `keep-alive`.
+    pub fn connection(&self) -> impl Iterator<Item = &str> {
+        self.get_list("Connection")
+    }
+
+    /// Whether `Connection` lists `token`, ignoring case.
+    pub fn connection_has(&self, token: &str) -> bool {
+        self.connection().any(|t| t.eq_ignore_ascii_case(token))
+    }
+
+    /// The elements of a list header with quality values, such as `Accept`
+    /// or `Accept-Encoding`, with their `q` (1 when absent), most preferred
+    /// first. Elements with `q=0` are refused by the client and left out.
+    pub fn quality_list<'h>(&'h self, name: &'h str) -> Vec<(&'h str, f32)> {
+        let mut items: Vec<(&str, f32)> = self.get_list(name)
+            .map(|item| {
+                let mut params = item.split(';');
+                let value = params.next().unwrap_or_default().trim();
+                let q = params
+                    .filter_map(|p| p.trim().strip_prefix("q=").or_else(|| p.trim().strip_prefix("Q=")))
+                    .find_map(|q| q.trim().parse::<f32>().ok())
+                    .unwrap_or(1.0);
+                (value, q)
+            })
+            .filter(|(_, q)| *q > 0.0)
+            .collect();
+        items.sort_by(|a, b| b.1.total_cmp(&a.1));
+        items
+    }
+
+    /// The media ranges of `Accept`, see [`Self::quality_list`].
+    pub fn accept(&self) -> Vec<(&str, f32)> {
+        self.quality_list("Accept")
+    }
+
+}
+
+impl IntoIterator for HeaderMap {
+    type Item = (String, String);
+    type IntoIter = std::vec::IntoIter<(String, String)>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.entries.into_iter()
+    }
+}
+
+impl<K: Into<String>, V: Into<String>> FromIterator<(K, V)> for HeaderMap {
+    fn from_iter<T: IntoIterator<Item = (K, V)>>(iter: T) -> Self {
+        HeaderMap {
+            entries: iter.into_iter().map(|(k, v)| (k.into(), v.into())).collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_header_map() {
+        let mut h = HeaderMap::new();
+        h.append("Set-Cookie", "a=1");
+        h.append("Content-Type", "text/html; charset=utf-8");
+        h.append("set-cookie", "b=2");
+        assert_eq!(h.get("SET-COOKIE"), Some("a=1"));
+        assert_eq!(h.get_all("Set-Cookie").collect::<Vec<_>>(), vec!["a=1", "b=2"]);
+        assert_eq!(h.content_type(), Some("text/html"));
+
+        assert_eq!(h.insert("SET-COOKIE", "c=3"), Some("a=1".to_string()));
+        assert_eq!(h.iter().collect::<Vec<_>>(), vec![("Set-Cookie", "c=3"), ("Content-Type", "text/html; charset=utf-8")]);
+        assert_eq!(h.remove("content-type"), Some("text/html; charset=utf-8".to_string()));
+        assert_eq!(h.len(), 1);
+    }
+
+    #[test]
+    fn test_typed_headers() {
+        let h: HeaderMap = [
+            ("Connection", "Keep-Alive, Upgrade"),
+            ("Content-Length", "12"),
+            ("content-length", "12"),
+            ("Accept", "text/html;q=0.5, application/json, */*;q=0.1, image/png;q=0"),
+        ].into_iter().collect();
+        assert!(h.connection_has("keep-alive"));
+        assert!(!h.connection_has("close"));
+        assert_eq!(h.content_length(), Some(12));
+        assert_eq!(h.accept(), vec![("application/json", 1.0), ("text/html", 0.5), ("*/*", 0.1)]);
+
+        let h: HeaderMap = [("Content-Length", "12"), ("Content-Length", "13")].into_iter().collect();
+        assert_eq!(h.content_length(), None);
+        let h: HeaderMap = [("Content-Length", "-1")].into_iter().collect();
+        assert_eq!(h.content_length(), None);
+    }
+}
@@ -0,0 +1,75 @@
+use std::{error::Error, fmt::Display, io};
+
+use super::{HttpResponse, ReqError};
+
+/// Bounds on the size of a request, so that a client cannot make the server
+/// buffer without end. Line lengths do not count the CRLF ending them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestLimits {
+    /// Longest request line, e.g. `GET /index.html HTTP/1.1`; answered with 414.
+    pub max_request_line: usize,
+    /// Longest single header field; answered with 431. Also bounds the lines
+    /// of a chunked body, see [`HttpRequest::set_body_limit`](super::HttpRequest::set_body_limit).
+    pub max_header_line: usize,
+    /// Most bytes of header fields in total, CRLFs included; answered with 431.
+    pub max_header_bytes: usize,
+    /// Most header fields; answered with 431. Trailer fields count as well.
+    pub max_headers: usize,
+    /// Largest body, including the trailers of a chunked one; answered with 413.
+    pub max_body: u64,
+}
+
+impl Default for RequestLimits {
+    fn default() -> Self {
+        RequestLimits {
+            max_request_line: 8 * 1024,
+            max_header_line: 8 * 1024,
+            max_header_bytes: 64 * 1024,
+            max_headers: 100,
+            max_body: 16 * 1024 * 1024,
+        }
+    }
+}
+
+/// The error inside the `io::Error` a body reader fails with once the body
+/// goes over [`RequestLimits::max_body`].
+#[derive(Debug)]
+pub(crate) struct BodyTooLarge;
+
+impl Display for BodyTooLarge {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("request body too large")
+    }
+}
+
+impl Error for BodyTooLarge {}
+
+impl BodyTooLarge {
+
+    pub(crate) fn into_io() -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, BodyTooLarge)
+    }
+
+}
+
+/// Wraps an error from reading the body, telling a body over the limit apart.
+pub(crate) fn body_error(e: io::Error) -> ReqError {
+    if e.get_ref().is_some_and(|inner| inner.is::<BodyTooLarge>()) {
+        ReqError::BodyTooLarge
+    } else {
+        ReqError::IOError(e)
+    }
+}
+
+/// The response to a request over one of the limits, closing the connection
+/// as the rest of the request is left unread; `None` for other errors.
+pub fn limit_response(e: &ReqError) -> Option<HttpResponse> {
+    let mut resp = match e {
+        ReqError::UriTooLong => HttpResponse::create_414_uri_too_long(),
+        ReqError::HeaderTooLarge => HttpResponse::create_431_request_header_fields_too_large(),
+        ReqError::BodyTooLarge => HttpResponse::create_413_content_too_large(),
+        _ => return None,
+    };
+    resp.headers.insert("Connection", "close");
+    Some(resp)
+}
@@ -1,3 +1,7 @@
+mod headers;
+
+pub use headers::*;
+
 mod request;
 
 pub use request::*;
@@ -8,4 +12,36 @@ pub use read_until_crlf::*;
 
 mod response;
 
-pub use response::*;
\ No newline at end of file
+pub use response::*;
+
+mod body;
+
+pub use body::*;
+
+mod percent;
+
+pub use percent::*;
+
+mod mime;
+
+pub use mime::*;
+
+mod range;
+
+pub use range::*;
+
+mod conditional;
+
+pub use conditional::*;
+
+mod query;
+
+pub use query::*;
+
+mod normalize;
+
+pub use normalize::*;
+
+mod limits;
+
+pub use limits::*;
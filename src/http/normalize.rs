@@ -0,0 +1,98 @@
+use super::percent::hex_val;
+
+/// Normalizes the `%XX` escapes of a url path as RFC 3986 section 6.2.2
+/// describes: escaped unreserved characters are decoded, other escapes get
+/// upper case hex digits. Escapes of reserved characters, `%2F` in
+/// particular, stay escaped, so that they can not turn into separators.
+/// Returns `None` for a malformed escape.
+pub fn normalize_percent(path: &str) -> Option<String> {
+    let bytes = path.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0_usize;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hi = *bytes.get(i + 1)?;
+            let lo = *bytes.get(i + 2)?;
+            let b = hex_val(hi)? << 4 | hex_val(lo)?;
+            if b.is_ascii_alphanumeric() || matches!(b, b'-' | b'.' | b'_' | b'~') {
+                out.push(b);
+            } else {
+                out.extend([b'%', hi.to_ascii_uppercase(), lo.to_ascii_uppercase()]);
+            }
+            i += 3;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+    // Only ASCII was decoded, so multi-byte characters are left whole.
+    String::from_utf8(out).ok()
+}
+
+/// Resolves the `.` and `..` segments of an absolute path, as in RFC 3986
+/// section 5.2.4; `..` at the root stays at the root.
+pub fn remove_dot_segments(path: &str) -> String {
+    let Some(rest) = path.strip_prefix('/') else {
+        return path.to_string();
+    };
+    let segs: Vec<&str> = rest.split('/').collect();
+    let mut out: Vec<&str> = Vec::with_capacity(segs.len());
+    for (i, seg) in segs.iter().enumerate() {
+        let last = i + 1 == segs.len();
+        match *seg {
+            "." => {},
+            ".." => {
+                out.pop();
+            },
+            seg => {
+                out.push(seg);
+                continue;
+            },
+        }
+        // A path ending in a dot segment names a directory.
+        if last {
+            out.push("");
+        }
+    }
+    format!("/{}", out.join("/"))
+}
+
+/// Replaces each run of `/` with a single one.
+pub fn merge_slashes(path: &str) -> String {
+    let mut out = String::with_capacity(path.len());
+    for c in path.chars() {
+        if c != '/' || !out.ends_with('/') {
+            out.push(c);
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_percent() {
+        assert_eq!(normalize_percent("/%7euser/%2e%2E/a%2fb%3a").as_deref(), Some("/~user/../a%2Fb%3A"));
+        assert_eq!(normalize_percent("/caf%C3%A9/你").as_deref(), Some("/caf%C3%A9/你"));
+        assert_eq!(normalize_percent("/bad%2"), None);
+        assert_eq!(normalize_percent("/bad%g0"), None);
+    }
+
+    #[test]
+    fn test_remove_dot_segments() {
+        assert_eq!(remove_dot_segments("/a/./b/../c"), "/a/c");
+        assert_eq!(remove_dot_segments("/a/b/.."), "/a/");
+        assert_eq!(remove_dot_segments("/a/b/."), "/a/b/");
+        assert_eq!(remove_dot_segments("/../../etc/passwd"), "/etc/passwd");
+        assert_eq!(remove_dot_segments("/.."), "/");
+        assert_eq!(remove_dot_segments("/a/"), "/a/");
+        assert_eq!(remove_dot_segments("/a/..b/c"), "/a/..b/c");
+    }
+
+    #[test]
+    fn test_merge_slashes() {
+        assert_eq!(merge_slashes("//a///b/"), "/a/b/");
+    }
+}
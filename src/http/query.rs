@@ -0,0 +1,65 @@
+use super::form_decode;
+
+/// The decoded `key=value` pairs of a query string, in the order they were sent.
+///
+/// Keys may repeat; a key without `=` has an empty value. A component with a
+/// malformed escape is kept as it was sent.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Query {
+    pairs: Vec<(String, String)>,
+}
+
+impl Query {
+
+    pub fn parse(query: &str) -> Self {
+        let decode = |s: &str| form_decode(s).unwrap_or_else(|| s.to_string());
+        let pairs = query
+            .split('&')
+            .filter(|pair| !pair.is_empty())
+            .map(|pair| match pair.split_once('=') {
+                Some((k, v)) => (decode(k), decode(v)),
+                None => (decode(pair), String::new()),
+            })
+            .collect();
+        Query { pairs }
+    }
+
+    /// The first value of `key`.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.pairs.iter().find(|(k, _)| k == key).map(|(_, v)| v.as_str())
+    }
+
+    /// Every value of `key`, e.g. both tags in `?tag=a&tag=b`.
+    pub fn get_all<'q>(&'q self, key: &'q str) -> impl Iterator<Item = &'q str> {
+        self.pairs.iter().filter(move |(k, _)| k == key).map(|(_, v)| v.as_str())
+    }
+
+    pub fn contains(&self, key: &str) -> bool {
+        self.get(key).is_some()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.pairs.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pairs.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_query_parse() {
+        let q = Query::parse("tag=a&name=J%C3%BCrgen+M&tag=b&flag&&bad=%zz");
+        assert_eq!(q.get("name"), Some("Jürgen M"));
+        assert_eq!(q.get_all("tag").collect::<Vec<_>>(), vec!["a", "b"]);
+        assert_eq!(q.get("flag"), Some(""));
+        assert_eq!(q.get("bad"), Some("%zz"));
+        assert_eq!(q.get("missing"), None);
+        assert_eq!(q.iter().count(), 5);
+        assert!(Query::parse("").is_empty());
+    }
+}
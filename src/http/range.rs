@@ -0,0 +1,100 @@
+use std::ops::Range;
+
+/// Most ranges honoured in one request; more than that is served as a whole.
+const MAX_RANGES: usize = 16;
+
+/// Outcome of evaluating a `Range` header against a representation of `len` bytes.
+#[derive(Debug, PartialEq, Eq)]
+pub enum ByteRanges {
+    /// Not a valid `bytes` range: serve the full representation.
+    Ignore,
+    /// Valid, but no range overlaps the representation: answer 416.
+    Unsatisfiable,
+    /// Satisfiable ranges, as half-open byte intervals.
+    Satisfiable(Vec<Range<u64>>),
+}
+
+/// Parses `Range: bytes=...` as in RFC 9110 14.1.2, supporting
+/// `first-last`, `first-` and suffix `-length` specs.
+pub fn parse_byte_ranges(value: &str, len: u64) -> ByteRanges {
+    let specs = match value.trim().split_once('=') {
+        Some((unit, specs)) if unit.trim().eq_ignore_ascii_case("bytes") => specs,
+        _ => return ByteRanges::Ignore,
+    };
+    let mut ranges = Vec::new();
+    let mut count = 0_usize;
+    for spec in specs.split(',') {
+        let spec = spec.trim();
+        if spec.is_empty() {
+            continue;
+        }
+        count += 1;
+        if count > MAX_RANGES {
+            return ByteRanges::Ignore;
+        }
+        let (first, last) = match spec.split_once('-') {
+            Some(fl) => fl,
+            None => return ByteRanges::Ignore,
+        };
+        let (first, last) = (first.trim(), last.trim());
+        let range = if first.is_empty() {
+            let suffix: u64 = match last.parse() {
+                Ok(n) => n,
+                Err(_) => return ByteRanges::Ignore,
+            };
+            if suffix == 0 {
+                continue;
+            }
+            len.saturating_sub(suffix)..len
+        } else {
+            let first: u64 = match first.parse() {
+                Ok(n) => n,
+                Err(_) => return ByteRanges::Ignore,
+            };
+            let end = if last.is_empty() {
+                len
+            } else {
+                match last.parse::<u64>() {
+                    Ok(n) if n >= first => n.saturating_add(1).min(len),
+                    _ => return ByteRanges::Ignore,
+                }
+            };
+            first..end
+        };
+        if range.start < range.end {
+            ranges.push(range);
+        }
+    }
+    if count == 0 {
+        ByteRanges::Ignore
+    } else if ranges.is_empty() {
+        ByteRanges::Unsatisfiable
+    } else {
+        ByteRanges::Satisfiable(ranges)
+    }
+}
+
+pub(crate) fn content_range(range: &Range<u64>, len: u64) -> String {
+    format!("bytes {}-{}/{}", range.start, range.end - 1, len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[allow(clippy::single_range_in_vec_init)]
+    fn test_parse_byte_ranges() {
+        assert_eq!(parse_byte_ranges("bytes=0-499", 1000), ByteRanges::Satisfiable(vec![0..500]));
+        assert_eq!(parse_byte_ranges("bytes=500-", 1000), ByteRanges::Satisfiable(vec![500..1000]));
+        assert_eq!(parse_byte_ranges("bytes=-200", 1000), ByteRanges::Satisfiable(vec![800..1000]));
+        assert_eq!(parse_byte_ranges("bytes=-2000", 1000), ByteRanges::Satisfiable(vec![0..1000]));
+        assert_eq!(parse_byte_ranges("bytes=900-2000", 1000), ByteRanges::Satisfiable(vec![900..1000]));
+        assert_eq!(parse_byte_ranges("bytes=0-0, -1", 1000), ByteRanges::Satisfiable(vec![0..1, 999..1000]));
+        assert_eq!(parse_byte_ranges("bytes=1000-", 1000), ByteRanges::Unsatisfiable);
+        assert_eq!(parse_byte_ranges("bytes=-0", 1000), ByteRanges::Unsatisfiable);
+        assert_eq!(parse_byte_ranges("bytes=5-1", 1000), ByteRanges::Ignore);
+        assert_eq!(parse_byte_ranges("items=0-1", 1000), ByteRanges::Ignore);
+        assert_eq!(parse_byte_ranges("bytes=abc", 1000), ByteRanges::Ignore);
+    }
+}
@@ -14,16 +14,18 @@ fn find_crlf_in_bytes(bytes: &[u8]) -> Option<usize> {
         ExpectLF,
     }
     let mut state = State::ExpectCR;
-    for i in 0..bytes.len() {
+    for (i, &b) in bytes.iter().enumerate() {
         match state {
             State::ExpectCR => {
-                if bytes[i] == b'\r' {
+                if b == b'\r' {
                     state = State::ExpectLF;
                 }
             },
             State::ExpectLF => {
-                if bytes[i] == b'\n' {
+                if b == b'\n' {
                     return Some(i-1);
+                } else if b != b'\r' {
+                    state = State::ExpectCR;
                 }
             }
         }
@@ -42,6 +44,8 @@ pin_project! {
         // The number of bytes appended to buf. This can be less than buf.len() if
         // the buffer was not empty when the operation was started.
         read: usize,
+        // The most bytes to append before giving up on finding the CRLF.
+        limit: usize,
         // Make this future `!Unpin` for compatibility with async trait methods.
         #[pin]
         _pin: PhantomPinned,
@@ -52,6 +56,20 @@ pub(crate) fn read_until_crlf<'a, R>(
     reader: &'a mut R,
     buf: &'a mut Vec<u8>,
 ) -> ReadUntilCrlf<'a, R>
+where
+    R: AsyncBufRead + ?Sized + Unpin,
+{
+    read_until_crlf_limited(reader, buf, usize::MAX)
+}
+
+/// Like [`read_until_crlf`], but stops after appending `limit` bytes. The
+/// line was cut short when `limit` bytes were read and `buf` does not end
+/// with a CRLF.
+pub(crate) fn read_until_crlf_limited<'a, R>(
+    reader: &'a mut R,
+    buf: &'a mut Vec<u8>,
+    limit: usize,
+) -> ReadUntilCrlf<'a, R>
 where
     R: AsyncBufRead + ?Sized + Unpin,
 {
@@ -59,6 +77,7 @@ where
         reader,
         buf,
         read: 0,
+        limit,
         _pin: PhantomPinned,
     }
 }
@@ -68,16 +87,18 @@ pub(super) fn read_until_crlf_internal<R: AsyncBufRead + ?Sized>(
     cx: &mut Context<'_>,
     buf: &mut Vec<u8>,
     read: &mut usize,
+    limit: usize,
 ) -> Poll<io::Result<usize>> {
     loop {
         let (done, used) = {
             let available = ready!(reader.as_mut().poll_fill_buf(cx))?;
+            let available = &available[..available.len().min(limit - *read)];
             if let Some(i) = find_crlf_in_bytes(available) {
                 buf.extend_from_slice(&available[..=i + 1]);
                 (true, i + 2)
-            } else if buf.len() > 0
-                && available.len() > 0
-                && buf[0] == b'\r'
+            } else if !buf.is_empty()
+                && !available.is_empty()
+                && buf.last() == Some(&b'\r')
                 && available[0] == b'\n'
             {
                 buf.push(available[0]);
@@ -100,7 +121,7 @@ impl<R: AsyncBufRead + ?Sized + Unpin> Future for ReadUntilCrlf<'_, R> {
 
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
         let me = self.project();
-        read_until_crlf_internal(Pin::new(*me.reader), cx, me.buf, me.read)
+        read_until_crlf_internal(Pin::new(*me.reader), cx, me.buf, me.read, *me.limit)
     }
 }
 
@@ -113,6 +134,31 @@ pub trait AsyncBufReadUtilCrlf: AsyncBufRead {
         read_until_crlf(self, buf)
     }
 
+    fn read_until_crlf_limited<'a>(&'a mut self, buf: &'a mut Vec<u8>, limit: usize) -> ReadUntilCrlf<'a, Self>
+    where
+        Self: Unpin,
+    {
+        read_until_crlf_limited(self, buf, limit)
+    }
+
 }
 
 impl<R: AsyncBufRead + ?Sized> AsyncBufReadUtilCrlf for R {}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::BufReader;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_split_crlf() {
+        let mut rx = BufReader::with_capacity(2, &b"ab\rc\r\nd\r\n"[..]);
+        let mut buf = Vec::new();
+        assert_eq!(rx.read_until_crlf(&mut buf).await.unwrap(), 6);
+        assert_eq!(buf, b"ab\rc\r\n");
+        buf.clear();
+        assert_eq!(rx.read_until_crlf(&mut buf).await.unwrap(), 3);
+        assert_eq!(buf, b"d\r\n");
+    }
+}
@@ -1,11 +1,53 @@
-use std::{collections::HashMap, fmt::{Debug, Display}};
+use std::{any::{Any, TypeId}, cell::OnceCell, collections::HashMap, fmt::{Debug, Display}, str::FromStr, sync::{Arc, OnceLock}};
 
-use tokio::io::{AsyncRead, AsyncBufRead};
+use tokio::io::{self, AsyncBufRead, AsyncReadExt};
 
-use super::AsyncBufReadUtilCrlf;
+use super::{body_error, AsyncBufReadUtilCrlf, BodyKind, BodyReader, ChunkedState, HeaderMap, Query, RequestLimits};
 
 
-#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+/// A method outside the standard ones, such as WebDAV's `PROPFIND`.
+///
+/// Stored inline so that [`Method`] stays `Copy`; names longer than
+/// [`ExtensionMethod::MAX_LEN`] bytes are not accepted.
+#[derive(PartialEq, Eq, Clone, Copy, Hash)]
+pub struct ExtensionMethod {
+    len: u8,
+    buf: [u8; ExtensionMethod::MAX_LEN],
+}
+
+impl ExtensionMethod {
+
+    pub const MAX_LEN: usize = 31;
+
+    /// Accepts a method `token` as in RFC 9110 9.1.
+    pub fn new(s: &str) -> Option<Self> {
+        let bytes = s.as_bytes();
+        if bytes.is_empty() || bytes.len() > Self::MAX_LEN || !bytes.iter().all(|&b| is_tchar(b)) {
+            return None;
+        }
+        let mut buf = [0_u8; Self::MAX_LEN];
+        buf[..bytes.len()].copy_from_slice(bytes);
+        Some(ExtensionMethod { len: bytes.len() as u8, buf })
+    }
+
+    pub fn as_str(&self) -> &str {
+        // Only ever built from a `&str` of ASCII token characters.
+        std::str::from_utf8(&self.buf[..self.len as usize]).unwrap_or_default()
+    }
+
+}
+
+impl Debug for ExtensionMethod {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Debug::fmt(self.as_str(), f)
+    }
+}
+
+fn is_tchar(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b)
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
 pub enum Method {
     GET,
     POST,
@@ -15,10 +57,27 @@ pub enum Method {
     HEAD,
     OPTIONS,
     TRACE,
-    CONNECT
+    CONNECT,
+    Extension(ExtensionMethod),
 }
 
 impl Method {
+
+    pub const STANDARD: [Method; 9] = [
+        Method::GET,
+        Method::POST,
+        Method::PUT,
+        Method::DELETE,
+        Method::PATCH,
+        Method::HEAD,
+        Method::OPTIONS,
+        Method::TRACE,
+        Method::CONNECT,
+    ];
+
+    /// Parses a method name; names are case-sensitive, and any valid token
+    /// other than the standard ones becomes an [`Method::Extension`].
+    #[allow(clippy::should_implement_trait)]
     pub fn from_str(s: &str) -> Option<Method> {
         match s {
             "GET" => Some(Method::GET),
@@ -30,11 +89,11 @@ impl Method {
             "OPTIONS" => Some(Method::OPTIONS),
             "TRACE" => Some(Method::TRACE),
             "CONNECT" => Some(Method::CONNECT),
-            _ => None
+            _ => ExtensionMethod::new(s).map(Method::Extension),
         }
     }
 
-    pub fn to_str(&self) -> &'static str {
+    pub fn to_str(&self) -> &str {
         match self {
             Method::GET => "GET",
             Method::POST => "POST",
@@ -45,8 +104,14 @@ impl Method {
             Method::OPTIONS => "OPTIONS",
             Method::TRACE => "TRACE",
             Method::CONNECT => "CONNECT",
+            Method::Extension(ext) => ext.as_str(),
         }
     }
+
+    /// Position among [`Method::STANDARD`], `None` for extension methods.
+    pub fn standard_index(&self) -> Option<usize> {
+        Method::STANDARD.iter().position(|m| m == self)
+    }
 }
 
 impl Display for Method {
@@ -62,6 +127,7 @@ pub enum HttpVersion {
 }
 
 impl HttpVersion {
+    #[allow(clippy::should_implement_trait)]
     pub fn from_str(s: &str) -> Option<HttpVersion> {
         match s {
             "HTTP/1.0" => Some(HttpVersion::HTTP1_0),
@@ -90,14 +156,28 @@ pub enum ReqError {
     IOError(std::io::Error),
     EmptyReq,
     FmtError,
+    /// A url capture is missing or does not parse; holds a description for the client.
+    BadParam(String),
+    /// The request line is over [`RequestLimits::max_request_line`].
+    UriTooLong,
+    /// The header fields are over one of the header limits of [`RequestLimits`].
+    HeaderTooLarge,
+    /// The body is over [`RequestLimits::max_body`].
+    BodyTooLarge,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct HttpRequestHeader {
     pub method: Method,
     pub url: String,
     pub version: HttpVersion,
-    pub paras: HashMap<String, String>,
+    pub paras: HeaderMap,
+}
+
+impl Default for HttpRequestHeader {
+    fn default() -> Self {
+        HttpRequestHeader::new()
+    }
 }
 
 impl HttpRequestHeader {
@@ -106,17 +186,28 @@ impl HttpRequestHeader {
             method: Method::GET,
             url: String::new(),
             version: HttpVersion::HTTP1_1,
-            paras: HashMap::new(),
+            paras: HeaderMap::new(),
         }
     }
 
     pub async fn from_async_stream(buf_reader: &'_ mut (impl AsyncBufRead + Unpin + Send)) -> Result<Self, ReqError> {
+        Self::from_async_stream_with(buf_reader, &RequestLimits::default()).await
+    }
+
+    /// Parses a request header, failing with [`ReqError::UriTooLong`],
+    /// [`ReqError::HeaderTooLarge`] or [`ReqError::BodyTooLarge`] (for a
+    /// `Content-Length` over the limit) as soon as a limit is crossed.
+    pub async fn from_async_stream_with(buf_reader: &'_ mut (impl AsyncBufRead + Unpin + Send), limits: &RequestLimits) -> Result<Self, ReqError> {
     
         let mut request_line_buf = Vec::<u8>::with_capacity(1024);
 
-        if 0 == buf_reader.read_until_crlf(&mut request_line_buf).await.map_err(|e| {ReqError::IOError(e)})? {
+        let s = buf_reader.read_until_crlf_limited(&mut request_line_buf, limits.max_request_line + 2).await.map_err(|e| {ReqError::IOError(e)})?;
+        if 0 == s {
             return Err(ReqError::EmptyReq);
         }
+        if s == limits.max_request_line + 2 && !request_line_buf.ends_with(b"\r\n") {
+            return Err(ReqError::UriTooLong);
+        }
 
         let request_line ;
         unsafe {
@@ -129,12 +220,19 @@ impl HttpRequestHeader {
         req.url = split.next().ok_or(ReqError::FmtError)?.to_string();
         req.version = HttpVersion::from_str(split.next().ok_or(ReqError::FmtError)?).ok_or(ReqError::FmtError)?;
 
+        let mut header_bytes = 0_usize;
         loop {
             let mut header_buf = Vec::<u8>::new();
-            let s = buf_reader.read_until_crlf(&mut header_buf).await.map_err(|e| { ReqError::IOError(e) })?;
+            let s = buf_reader.read_until_crlf_limited(&mut header_buf, limits.max_header_line + 2).await.map_err(|e| { ReqError::IOError(e) })?;
             if s == 2 {
                 break;
             }
+            header_bytes = header_bytes.saturating_add(s);
+            if (s == limits.max_header_line + 2 && !header_buf.ends_with(b"\r\n"))
+                    || header_bytes > limits.max_header_bytes
+                    || req.paras.len() >= limits.max_headers {
+                return Err(ReqError::HeaderTooLarge);
+            }
             let header_line ;
             unsafe {
                 header_line = String::from_utf8_unchecked(header_buf);
@@ -142,23 +240,88 @@ impl HttpRequestHeader {
             let mut split = header_line.splitn(2, ':');
             let k = split.next().ok_or(ReqError::FmtError)?;
             let v = split.next().ok_or(ReqError::FmtError)?;
-            req.paras.insert(
-                k.to_string(),
-                v.trim().to_string()
-            );
+            req.paras.append(k, v.trim());
+        }
+
+        if req.paras.contains_key("Transfer-Encoding") {
+            // RFC 9112 6.1: a request with both framings is a smuggling attempt,
+            // and chunked has to be the final (here: only) coding we understand.
+            if req.paras.contains_key("Content-Length") || !req.is_chunked() {
+                return Err(ReqError::FmtError);
+            }
+        } else if req.get_para("Content-Length").is_some() && req.content_length().is_none() {
+            return Err(ReqError::FmtError);
+        } else if req.content_length().is_some_and(|len| len > limits.max_body) {
+            return Err(ReqError::BodyTooLarge);
         }
         
         Ok(req)
     }
 
+    /// Looks up a header ignoring the case of its name.
+    pub fn get_para(&self, name: &str) -> Option<&str> {
+        self.paras.get(name)
+    }
+
+    pub fn content_length(&self) -> Option<u64> {
+        self.paras.content_length()
+    }
 
+    pub fn is_chunked(&self) -> bool {
+        let mut codings = self.paras.get_list("Transfer-Encoding");
+        codings.next().is_some_and(|c| c.eq_ignore_ascii_case("chunked")) && codings.next().is_none()
+    }
+
+    /// The url up to the query string.
+    pub fn path(&self) -> &str {
+        self.url.split_once('?').map_or(&self.url, |(path, _)| path)
+    }
+
+    /// The raw query string, without the `?`.
+    pub fn query_str(&self) -> Option<&str> {
+        self.url.split_once('?').map(|(_, query)| query)
+    }
+
+}
+
+/// Room for the headers a request is rewritten to, see [`HttpRequest::modify_header`].
+///
+/// The captures handed to handlers borrow from the url of the header they
+/// were matched against, so every version of the header has to outlive the
+/// request; the processor keeps an arena per request for that.
+#[derive(Default)]
+pub struct HeaderArena {
+    header: OnceLock<HttpRequestHeader>,
+    next: OnceLock<Box<HeaderArena>>,
+}
+
+impl HeaderArena {
+
+    /// Stores `header` in the first free slot.
+    fn alloc(&self, mut header: HttpRequestHeader) -> &HttpRequestHeader {
+        let mut arena = self;
+        loop {
+            match arena.header.set(header) {
+                Ok(()) => return arena.header.get().expect("slot was just filled"),
+                Err(back) => header = back,
+            }
+            arena = arena.next.get_or_init(Box::default);
+        }
+    }
 
 }
 
 pub struct HttpRequest<'a> {
+    /// The header as the middleware and handlers further down see it, which
+    /// may differ from the one received, see [`Self::modify_header`].
     pub header: &'a HttpRequestHeader,
+    arena: Option<&'a HeaderArena>,
     url_paras: Option<Vec<&'a str>>,
+    para_names: Arc<[Option<String>]>,
+    query: OnceCell<Query>,
     body: Option<Vec<u8>>,
+    body_kind: BodyKind,
+    extensions: HashMap<TypeId, Box<dyn Any + Send + Sync>>,
     buf_reader: &'a mut (dyn AsyncBufRead + Unpin + Send), 
 }
 
@@ -174,9 +337,297 @@ impl<'a>  HttpRequest<'a> {
     pub fn new(header:&'a HttpRequestHeader ,rx: &'a mut (impl AsyncBufRead + Unpin + Send)) -> HttpRequest<'a>{
         HttpRequest {
             header,
+            arena: None,
             url_paras: None,
+            para_names: Arc::new([]),
+            query: OnceCell::new(),
             buf_reader: rx,
             body: None,
+            extensions: HashMap::new(),
+            body_kind: if header.is_chunked() {
+                BodyKind::Chunked(ChunkedState::new())
+            } else {
+                BodyKind::Length(header.content_length().unwrap_or(0))
+            },
+        }
+    }
+
+    /// Bounds a chunked body by `limits`: reading past `max_body`, a chunk
+    /// line over `max_header_line`, or more trailer fields than `max_headers`
+    /// leaves after the header fails with [`ReqError::BodyTooLarge`]. A
+    /// `Content-Length` over the limit is refused earlier, by
+    /// [`HttpRequestHeader::from_async_stream_with`].
+    pub fn set_body_limit(&mut self, limits: &RequestLimits) {
+        if let BodyKind::Chunked(state) = &mut self.body_kind {
+            state.set_limits(limits, self.header.paras.len());
+        }
+    }
+
+    /// Gives the request somewhere to keep the headers [`Self::modify_header`] rewrites it to.
+    pub fn set_header_arena(&mut self, arena: &'a HeaderArena) {
+        self.arena = Some(arena);
+    }
+
+    /// Changes the header for the middleware and handlers further down the
+    /// chain, e.g. to rewrite the url, the method or a header field; routing
+    /// that happens later uses the new url. The body keeps the framing it
+    /// was sent with, and the response is written for the header as received.
+    ///
+    /// # Panics
+    ///
+    /// Without an arena, see [`Self::set_header_arena`]; the processor gives every request one.
+    pub fn modify_header(&mut self, f: impl FnOnce(&mut HttpRequestHeader)) {
+        let arena = self.arena.expect("modify_header needs a header arena");
+        let mut header = self.header.clone();
+        f(&mut header);
+        self.header = arena.alloc(header);
+        self.query = OnceCell::new();
+    }
+
+    /// Records the captures of the matched route, so that handlers can look them up by name.
+    pub fn set_url_paras(&mut self, names: Arc<[Option<String>]>, captures: Vec<&'a str>) {
+        self.para_names = names;
+        self.url_paras = Some(captures);
+    }
+
+    /// The capture named `name` in the matched route's pattern, e.g. `id` in `/users/{id}`.
+    pub fn param(&self, name: &str) -> Option<&'a str> {
+        let i = self.para_names.iter().position(|n| n.as_deref() == Some(name))?;
+        self.url_paras.as_ref()?.get(i).copied()
+    }
+
+    /// Parses the capture named `name`; a missing or malformed capture is a
+    /// [`ReqError::BadParam`], which the router answers with 400.
+    pub fn param_as<T: FromStr>(&self, name: &str) -> Result<T, ReqError> {
+        let raw = self.param(name).ok_or_else(|| ReqError::BadParam(format!("missing url parameter `{}`", name)))?;
+        raw.parse().map_err(|_| ReqError::BadParam(format!("invalid url parameter `{}`: {:?}", name, raw)))
+    }
+
+    /// The decoded query string, parsed on first use.
+    pub fn query(&self) -> &Query {
+        self.query.get_or_init(|| Query::parse(self.header.query_str().unwrap_or_default()))
+    }
+
+    /// Parses the first value of the query parameter `key`, failing like [`Self::param_as`].
+    pub fn query_as<T: FromStr>(&self, key: &str) -> Result<T, ReqError> {
+        let raw = self.query().get(key).ok_or_else(|| ReqError::BadParam(format!("missing query parameter `{}`", key)))?;
+        raw.parse().map_err(|_| ReqError::BadParam(format!("invalid query parameter `{}`: {:?}", key, raw)))
+    }
+
+    /// Attaches a value to the request, e.g. for a middleware to pass the
+    /// authenticated user on to the handler. Replaces any value of the same type.
+    pub fn insert_extension<T: Send + Sync + 'static>(&mut self, value: T) -> Option<T> {
+        self.extensions.insert(TypeId::of::<T>(), Box::new(value))
+            .and_then(|old| old.downcast().ok().map(|old| *old))
+    }
+
+    pub fn extension<T: Send + Sync + 'static>(&self) -> Option<&T> {
+        self.extensions.get(&TypeId::of::<T>())?.downcast_ref()
+    }
+
+    pub fn remove_extension<T: Send + Sync + 'static>(&mut self) -> Option<T> {
+        self.extensions.remove(&TypeId::of::<T>())
+            .and_then(|old| old.downcast().ok().map(|old| *old))
+    }
+
+    /// Streams the part of the body that has not been read yet.
+    pub fn body_reader(&mut self) -> BodyReader<'_> {
+        BodyReader::new(&mut *self.buf_reader, &mut self.body_kind)
+    }
+
+    /// Trailer fields of a chunked body, available once the body has been read to the end.
+    pub fn trailers(&self) -> Option<&HeaderMap> {
+        match &self.body_kind {
+            BodyKind::Chunked(state) if state.is_done() => Some(&state.trailers),
+            _ => None,
         }
     }
+
+    /// Reads the whole body. The bytes are kept, so later calls are free.
+    /// If the body was partially streamed through [`Self::body_reader`], only the rest is returned.
+    pub async fn body_bytes(&mut self) -> Result<&[u8], ReqError> {
+        if self.body.is_none() {
+            let mut buf = Vec::with_capacity(usize::try_from(self.body_kind.size_hint()).unwrap_or(0).min(64 * 1024));
+            self.body_reader().read_to_end(&mut buf).await.map_err(body_error)?;
+            self.body = Some(buf);
+        }
+        Ok(self.body.as_deref().unwrap_or_default())
+    }
+
+    pub async fn body_string(&mut self) -> Result<String, ReqError> {
+        let bytes = self.body_bytes().await?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| { ReqError::FmtError })
+    }
+
+    /// Discards whatever is left of the body, so that the next request
+    /// on the connection starts at the right place.
+    pub async fn drain_body(&mut self) -> Result<(), ReqError> {
+        io::copy_buf(&mut self.body_reader(), &mut io::sink()).await.map_err(body_error)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn parse(raw: &'static [u8]) -> (HttpRequestHeader, &'static [u8]) {
+        let mut rx = raw;
+        let header = HttpRequestHeader::from_async_stream(&mut rx).await.unwrap();
+        (header, rx)
+    }
+
+    #[tokio::test]
+    async fn test_body_content_length() {
+        let (header, mut rx) = parse(b"POST /a HTTP/1.1\r\ncontent-length: 5\r\n\r\nhelloGET /b HTTP/1.1\r\n\r\n").await;
+        let mut req = HttpRequest::new(&header, &mut rx);
+        assert_eq!(req.body_string().await.unwrap(), "hello");
+        assert_eq!(req.body_bytes().await.unwrap(), b"hello");
+        drop(req);
+        let next = HttpRequestHeader::from_async_stream(&mut rx).await.unwrap();
+        assert_eq!(next.url, "/b");
+    }
+
+    #[tokio::test]
+    async fn test_body_drain() {
+        let (header, mut rx) = parse(b"POST /a HTTP/1.1\r\nContent-Length: 10\r\n\r\n0123456789GET /b HTTP/1.1\r\n\r\n").await;
+        let mut req = HttpRequest::new(&header, &mut rx);
+        let mut buf = [0_u8; 4];
+        req.body_reader().read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"0123");
+        req.drain_body().await.unwrap();
+        drop(req);
+        let next = HttpRequestHeader::from_async_stream(&mut rx).await.unwrap();
+        assert_eq!(next.url, "/b");
+    }
+
+    #[tokio::test]
+    async fn test_body_truncated() {
+        let (header, mut rx) = parse(b"POST /a HTTP/1.1\r\nContent-Length: 10\r\n\r\n0123").await;
+        let mut req = HttpRequest::new(&header, &mut rx);
+        assert!(matches!(req.body_bytes().await, Err(ReqError::IOError(_))));
+    }
+
+    #[tokio::test]
+    async fn test_body_chunked() {
+        let (header, mut rx) = parse(b"POST /a HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n\
+            5;name=value\r\nhello\r\n7\r\n, world\r\n0\r\nChecksum: abc\r\n\r\nGET /b HTTP/1.1\r\n\r\n").await;
+        let mut req = HttpRequest::new(&header, &mut rx);
+        assert!(req.trailers().is_none());
+        assert_eq!(req.body_string().await.unwrap(), "hello, world");
+        assert_eq!(req.trailers().unwrap().get("Checksum"), Some("abc"));
+        drop(req);
+        let next = HttpRequestHeader::from_async_stream(&mut rx).await.unwrap();
+        assert_eq!(next.url, "/b");
+    }
+
+    #[tokio::test]
+    async fn test_body_chunked_drain() {
+        let (header, mut rx) = parse(b"POST /a HTTP/1.1\r\ntransfer-encoding: Chunked\r\n\r\n\
+            a\r\n0123456789\r\n0\r\n\r\nGET /b HTTP/1.1\r\n\r\n").await;
+        let mut req = HttpRequest::new(&header, &mut rx);
+        req.drain_body().await.unwrap();
+        drop(req);
+        let next = HttpRequestHeader::from_async_stream(&mut rx).await.unwrap();
+        assert_eq!(next.url, "/b");
+    }
+
+    #[tokio::test]
+    async fn test_body_chunked_malformed() {
+        let (header, mut rx) = parse(b"POST /a HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\nzz\r\nhello\r\n0\r\n\r\n").await;
+        let mut req = HttpRequest::new(&header, &mut rx);
+        assert!(matches!(req.body_bytes().await, Err(ReqError::IOError(_))));
+        let (header, mut rx) = parse(b"POST /a HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhelloXX0\r\n\r\n").await;
+        let mut req = HttpRequest::new(&header, &mut rx);
+        assert!(matches!(req.body_bytes().await, Err(ReqError::IOError(_))));
+    }
+
+    #[tokio::test]
+    async fn test_smuggling_rejected() {
+        let mut rx: &[u8] = b"POST /a HTTP/1.1\r\nContent-Length: 5\r\nTransfer-Encoding: chunked\r\n\r\n";
+        assert!(matches!(HttpRequestHeader::from_async_stream(&mut rx).await, Err(ReqError::FmtError)));
+        let mut rx: &[u8] = b"POST /a HTTP/1.1\r\nTransfer-Encoding: gzip\r\n\r\n";
+        assert!(matches!(HttpRequestHeader::from_async_stream(&mut rx).await, Err(ReqError::FmtError)));
+    }
+
+    #[test]
+    fn test_method_from_str() {
+        assert_eq!(Method::from_str("CONNECT"), Some(Method::CONNECT));
+        let propfind = Method::from_str("PROPFIND").unwrap();
+        assert_eq!(propfind.to_str(), "PROPFIND");
+        assert_eq!(propfind.standard_index(), None);
+        assert_ne!(Method::from_str("get"), Some(Method::GET));
+        assert_eq!(Method::from_str("BAD METHOD"), None);
+        assert_eq!(Method::from_str(""), None);
+        assert_eq!(Method::from_str(&"X".repeat(ExtensionMethod::MAX_LEN + 1)), None);
+    }
+
+    #[tokio::test]
+    async fn test_bad_content_length() {
+        let mut rx: &[u8] = b"POST /a HTTP/1.1\r\nContent-Length: abc\r\n\r\n";
+        assert!(matches!(HttpRequestHeader::from_async_stream(&mut rx).await, Err(ReqError::FmtError)));
+        let mut rx: &[u8] = b"POST /a HTTP/1.1\r\nContent-Length: +5\r\n\r\nhello";
+        assert!(matches!(HttpRequestHeader::from_async_stream(&mut rx).await, Err(ReqError::FmtError)));
+    }
+
+    #[tokio::test]
+    async fn test_repeated_headers() {
+        let (header, _) = parse(b"GET /a HTTP/1.1\r\nconnection: Upgrade\r\nAccept: text/html\r\nCONNECTION: close\r\naccept: */*;q=0.1\r\n\r\n").await;
+        assert!(header.paras.connection_has("Close"));
+        assert_eq!(header.get_para("Connection"), Some("Upgrade"));
+        assert_eq!(header.paras.get_all("Accept").collect::<Vec<_>>(), vec!["text/html", "*/*;q=0.1"]);
+    }
+
+    #[tokio::test]
+    async fn test_limits() {
+        let limits = RequestLimits {
+            max_request_line: 16,
+            max_header_line: 20,
+            max_header_bytes: 40,
+            max_headers: 3,
+            max_body: 8,
+        };
+        async fn parse_with(mut rx: &[u8], limits: &RequestLimits) -> Result<HttpRequestHeader, ReqError> {
+            HttpRequestHeader::from_async_stream_with(&mut rx, limits).await
+        }
+        assert!(parse_with(b"GET /a HTTP/1.1\r\nHost: example\r\n\r\n", &limits).await.is_ok());
+        assert!(matches!(parse_with(b"GET /abcdef HTTP/1.1\r\n\r\n", &limits).await, Err(ReqError::UriTooLong)));
+        assert!(matches!(parse_with(b"GET / HTTP/1.1\r\nX-Long: 0123456789abcdef\r\n\r\n", &limits).await, Err(ReqError::HeaderTooLarge)));
+        assert!(matches!(parse_with(b"GET / HTTP/1.1\r\nA: 1\r\nB: 2\r\nC: 3\r\nD: 4\r\n\r\n", &limits).await, Err(ReqError::HeaderTooLarge)));
+        assert!(matches!(parse_with(b"GET / HTTP/1.1\r\nA: 0123456789\r\nB: 0123456789\r\nC: 0123456789\r\n\r\n", &limits).await, Err(ReqError::HeaderTooLarge)));
+        assert!(matches!(parse_with(b"POST / HTTP/1.1\r\nContent-Length: 9\r\n\r\n", &limits).await, Err(ReqError::BodyTooLarge)));
+
+        let (header, mut rx) = parse(b"POST /a HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n5\r\nworld\r\n0\r\n\r\n").await;
+        let mut req = HttpRequest::new(&header, &mut rx);
+        req.set_body_limit(&limits);
+        assert!(matches!(req.body_bytes().await, Err(ReqError::BodyTooLarge)));
+
+        let limits = RequestLimits { max_body: 1024, ..limits };
+        let (header, mut rx) = parse(b"POST /a HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n0\r\nA: 1\r\nB: 2\r\nC: 3\r\n\r\n").await;
+        let mut req = HttpRequest::new(&header, &mut rx);
+        req.set_body_limit(&limits);
+        assert!(matches!(req.body_bytes().await, Err(ReqError::BodyTooLarge)));
+        let (header, mut rx) = parse(b"POST /a HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n0\r\nX-Long: 0123456789abcdef\r\n\r\n").await;
+        let mut req = HttpRequest::new(&header, &mut rx);
+        req.set_body_limit(&limits);
+        assert!(matches!(req.body_bytes().await, Err(ReqError::BodyTooLarge)));
+        let (header, mut rx) = parse(b"POST /a HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5;ext=0123456789abcdef\r\nhello\r\n0\r\n\r\n").await;
+        let mut req = HttpRequest::new(&header, &mut rx);
+        req.set_body_limit(&limits);
+        assert!(matches!(req.body_bytes().await, Err(ReqError::IOError(_))));
+        let (header, mut rx) = parse(b"POST /a HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n0\r\nA: 1\r\n\r\n").await;
+        let mut req = HttpRequest::new(&header, &mut rx);
+        req.set_body_limit(&limits);
+        assert_eq!(req.body_bytes().await.unwrap(), b"hello");
+        assert_eq!(req.trailers().unwrap().get("A"), Some("1"));
+    }
+
+    #[tokio::test]
+    async fn test_body_chunked_small_reads() {
+        let mut rx = io::BufReader::with_capacity(2, &b"POST /a HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n\
+            5\r\nhello\r\n7\r\n, world\r\n0\r\n\r\n"[..]);
+        let header = HttpRequestHeader::from_async_stream(&mut rx).await.unwrap();
+        let mut req = HttpRequest::new(&header, &mut rx);
+        assert_eq!(req.body_string().await.unwrap(), "hello, world");
+    }
 }
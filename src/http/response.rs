@@ -1,126 +1,711 @@
-use std::{collections::HashMap, io::Error};
+use std::{fmt::Debug, hash::{BuildHasher, RandomState}, io::{self, Cursor, Error, SeekFrom}, ops::Range, path::Path, pin::Pin, time::{SystemTime, UNIX_EPOCH}};
 
-use tokio::{fs::File, io::{AsyncReadExt, AsyncWriteExt, BufReader}};
+use futures::{stream::BoxStream, Stream, StreamExt};
+use httpdate::HttpDate;
+use tokio::{fs::File, io::{AsyncRead, AsyncReadExt, AsyncSeekExt, AsyncWriteExt}};
 
-use super::HttpVersion;
+use super::{content_range, evaluate_preconditions, mime_from_path, parse_byte_ranges, ByteRanges, HeaderMap, HttpRequestHeader, HttpVersion, Method, Precondition};
+
+const CHUNK_SIZE: usize = 16 * 1024;
+
+/// Headers a `304 Not Modified` keeps from the response it replaces (RFC 9110 15.4.5).
+const NOT_MODIFIED_HEADERS: [&str; 6] = ["Cache-Control", "Content-Location", "Date", "ETag", "Expires", "Vary"];
+
+/// Body of an [`HttpResponse`].
+///
+/// `write_to` picks the framing from the variant: a known length is sent with
+/// `Content-Length`, an unknown one with chunked encoding on HTTP/1.1 or by
+/// closing the connection on HTTP/1.0.
+pub enum ResponseBody {
+    Bytes(Vec<u8>),
+    Stream(BoxStream<'static, io::Result<Vec<u8>>>),
+    Reader(Pin<Box<dyn AsyncRead + Send>>, Option<u64>),
+}
+
+impl ResponseBody {
+
+    pub fn empty() -> Self {
+        ResponseBody::Bytes(Vec::new())
+    }
+
+    /// A body produced chunk by chunk, of unknown length.
+    pub fn stream(s: impl Stream<Item = io::Result<Vec<u8>>> + Send + 'static) -> Self {
+        ResponseBody::Stream(s.boxed())
+    }
+
+    /// A body read from `r` until EOF, of unknown length.
+    pub fn reader(r: impl AsyncRead + Send + 'static) -> Self {
+        ResponseBody::Reader(Box::pin(r), None)
+    }
+
+    /// A body of exactly `len` bytes read from `r`, e.g. a file.
+    pub fn sized_reader(r: impl AsyncRead + Send + 'static, len: u64) -> Self {
+        ResponseBody::Reader(Box::pin(r), Some(len))
+    }
+
+    /// Length of the body if it is known before sending.
+    pub fn len(&self) -> Option<u64> {
+        match self {
+            ResponseBody::Bytes(b) => Some(b.len() as u64),
+            ResponseBody::Stream(_) => None,
+            ResponseBody::Reader(_, len) => *len,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == Some(0)
+    }
+
+}
+
+impl Default for ResponseBody {
+    fn default() -> Self {
+        ResponseBody::empty()
+    }
+}
+
+impl Debug for ResponseBody {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ResponseBody::Bytes(b) => f.debug_tuple("Bytes").field(&b.len()).finish(),
+            ResponseBody::Stream(_) => f.write_str("Stream"),
+            ResponseBody::Reader(_, len) => f.debug_tuple("Reader").field(len).finish(),
+        }
+    }
+}
+
+impl From<Vec<u8>> for ResponseBody {
+    fn from(b: Vec<u8>) -> Self {
+        ResponseBody::Bytes(b)
+    }
+}
+
+impl From<String> for ResponseBody {
+    fn from(s: String) -> Self {
+        ResponseBody::Bytes(s.into_bytes())
+    }
+}
+
+impl From<&'static str> for ResponseBody {
+    fn from(s: &'static str) -> Self {
+        ResponseBody::Bytes(s.as_bytes().to_vec())
+    }
+}
+
+async fn write_chunk(tx: &mut (impl AsyncWriteExt + Unpin), chunk: &[u8]) -> Result<(), Error> {
+    if chunk.is_empty() {
+        return Ok(());
+    }
+    tx.write_all(format!("{:x}\r\n", chunk.len()).as_bytes()).await?;
+    tx.write_all(chunk).await?;
+    tx.write_all(b"\r\n").await
+}
 
 pub struct HttpResponse {
     pub version: HttpVersion,
     pub status_code: u32,
     pub status_msg: String,
-    pub headers: HashMap<String, String>,
-    pub body: Vec<u8>,
+    pub headers: HeaderMap,
+    pub body: ResponseBody,
 }
 
 impl HttpResponse {
 
     pub fn create_hello_response() -> Self {
-        let mut resp = HttpResponse {
-            body: "hello, world".as_bytes().to_vec(),
+        HttpResponse {
+            body: "hello, world".into(),
             ..HttpResponse::create_200_ok()
-        };
-
-        resp.headers.entry("Content-Length".to_string())
-                    .and_modify(|s| { *s = resp.body.len().to_string(); })
-                    .or_insert(resp.body.len().to_string());
-
-        resp
+        }
     }
 
     pub fn create_200_ok() -> Self {
-        let mut headers = HashMap::new();
+        let mut headers = HeaderMap::new();
         headers.insert("Content-Length".to_string(), "0".to_string());
         HttpResponse {
             version: HttpVersion::HTTP1_1,
             status_code: 200,
             status_msg: "OK".to_string(),
             headers,
-            body: Vec::new()
+            body: ResponseBody::empty(),
+        }
+    }
+
+    /// Redirects keeping the method and body; the caller sets `Location`.
+    pub fn create_308_permanent_redirect() -> Self {
+        let mut headers = HeaderMap::new();
+        headers.insert("Content-Length".to_string(), "0".to_string());
+        HttpResponse {
+            version: HttpVersion::HTTP1_1,
+            status_code: 308,
+            status_msg: "Permanent Redirect".to_string(),
+            headers,
+            body: ResponseBody::empty(),
+        }
+    }
+
+    pub fn create_400_bad_request() -> Self {
+        let mut headers = HeaderMap::new();
+        headers.insert("Content-Length".to_string(), "0".to_string());
+        HttpResponse {
+            version: HttpVersion::HTTP1_1,
+            status_code: 400,
+            status_msg: "Bad Request".to_string(),
+            headers,
+            body: ResponseBody::empty(),
+        }
+    }
+
+    pub fn create_403_forbidden() -> Self {
+        let mut headers = HeaderMap::new();
+        headers.insert("Content-Length".to_string(), "0".to_string());
+        HttpResponse {
+            version: HttpVersion::HTTP1_1,
+            status_code: 403,
+            status_msg: "Forbidden".to_string(),
+            headers,
+            body: ResponseBody::empty(),
+        }
+    }
+
+    pub fn create_304_not_modified() -> Self {
+        HttpResponse {
+            version: HttpVersion::HTTP1_1,
+            status_code: 304,
+            status_msg: "Not Modified".to_string(),
+            headers: HeaderMap::new(),
+            body: ResponseBody::empty(),
         }
     }
 
     pub fn create_404_not_found() -> Self {
-        let mut headers = HashMap::new();
+        let mut headers = HeaderMap::new();
         headers.insert("Content-Length".to_string(), "0".to_string());
         HttpResponse {
             version: HttpVersion::HTTP1_1,
             status_code: 404,
             status_msg: "Not Found".to_string(),
             headers,
-            body: Vec::new(),
+            body: ResponseBody::empty(),
         }
     }
 
     pub fn create_405_method_not_allowed() -> Self {
-        let mut headers = HashMap::new();
+        let mut headers = HeaderMap::new();
         headers.insert("Content-Length".to_string(), "0".to_string());
         HttpResponse {
             version: HttpVersion::HTTP1_1,
             status_code: 405,
             status_msg: "Method Not Allowed".to_string(),
             headers,
-            body: Vec::new()
+            body: ResponseBody::empty(),
+        }
+    }
+
+    pub fn create_412_precondition_failed() -> Self {
+        let mut headers = HeaderMap::new();
+        headers.insert("Content-Length".to_string(), "0".to_string());
+        HttpResponse {
+            version: HttpVersion::HTTP1_1,
+            status_code: 412,
+            status_msg: "Precondition Failed".to_string(),
+            headers,
+            body: ResponseBody::empty(),
+        }
+    }
+
+    pub fn create_413_content_too_large() -> Self {
+        let mut headers = HeaderMap::new();
+        headers.insert("Content-Length".to_string(), "0".to_string());
+        HttpResponse {
+            version: HttpVersion::HTTP1_1,
+            status_code: 413,
+            status_msg: "Content Too Large".to_string(),
+            headers,
+            body: ResponseBody::empty(),
+        }
+    }
+
+    pub fn create_414_uri_too_long() -> Self {
+        let mut headers = HeaderMap::new();
+        headers.insert("Content-Length".to_string(), "0".to_string());
+        HttpResponse {
+            version: HttpVersion::HTTP1_1,
+            status_code: 414,
+            status_msg: "URI Too Long".to_string(),
+            headers,
+            body: ResponseBody::empty(),
+        }
+    }
+
+    pub fn create_431_request_header_fields_too_large() -> Self {
+        let mut headers = HeaderMap::new();
+        headers.insert("Content-Length".to_string(), "0".to_string());
+        HttpResponse {
+            version: HttpVersion::HTTP1_1,
+            status_code: 431,
+            status_msg: "Request Header Fields Too Large".to_string(),
+            headers,
+            body: ResponseBody::empty(),
         }
     }
 
     pub fn create_500_internal_server_error() -> Self {
-        let mut headers = HashMap::new();
+        let mut headers = HeaderMap::new();
         headers.insert("Content-Length".to_string(), "0".to_string());
         HttpResponse {
             version: HttpVersion::HTTP1_1,
             status_code: 500,
             status_msg: "Internal Server Error".to_string(),
             headers,
-            body: Vec::new()
-        }
-    }
-
-    pub async fn from_html_file(path: &'static str) -> Self {
-        
-        if let Ok(html_file) = File::open(path).await {
-            let mut buf = Vec::<u8>::new();
-            let mut buf_reader = BufReader::new(html_file);
-            loop {
-                if let Ok(s) = buf_reader.read_buf(&mut buf).await {
-                    if s == 0 {
-                        let mut resp = HttpResponse {
-                            body: buf,
-                            ..Self::create_200_ok()
-                        };
-                        resp.headers.entry("Content-Length".to_string())
-                                    .and_modify(|s| { *s = resp.body.len().to_string(); })
-                                    .or_insert(resp.body.len().to_string());
-                        return resp;
+            body: ResponseBody::empty(),
+        }
+    }
+
+    pub fn create_503_service_unavailable() -> Self {
+        let mut headers = HeaderMap::new();
+        headers.insert("Content-Length".to_string(), "0".to_string());
+        HttpResponse {
+            version: HttpVersion::HTTP1_1,
+            status_code: 503,
+            status_msg: "Service Unavailable".to_string(),
+            headers,
+            body: ResponseBody::empty(),
+        }
+    }
+
+    /// Streams a regular file, with `Content-Type` guessed from its extension.
+    pub async fn from_file(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        let file = File::open(path).await?;
+        let meta = file.metadata().await?;
+        if !meta.is_file() {
+            return Err(Error::new(io::ErrorKind::InvalidInput, "not a regular file"));
+        }
+        let mut resp = HttpResponse {
+            body: ResponseBody::sized_reader(file, meta.len()),
+            ..Self::create_200_ok()
+        };
+        resp.headers.insert("Content-Type".to_string(), mime_from_path(path).to_string());
+        resp.headers.insert("Accept-Ranges".to_string(), "bytes".to_string());
+        if let Ok(modified) = meta.modified() {
+            let nanos = modified.duration_since(UNIX_EPOCH).map(|d| d.as_nanos()).unwrap_or(0);
+            resp.set_etag(&format!("{:x}-{:x}", meta.len(), nanos), false);
+            resp.set_last_modified(modified);
+        }
+        Ok(resp)
+    }
+
+    /// Sets the `ETag` validator; `tag` is the opaque part, without quotes.
+    pub fn set_etag(&mut self, tag: &str, weak: bool) {
+        let etag = if weak { format!("W/\"{}\"", tag) } else { format!("\"{}\"", tag) };
+        self.headers.insert("ETag".to_string(), etag);
+    }
+
+    pub fn set_last_modified(&mut self, modified: SystemTime) {
+        self.headers.insert("Last-Modified".to_string(), httpdate::fmt_http_date(modified));
+    }
+
+    fn last_modified(&self) -> Option<SystemTime> {
+        httpdate::parse_http_date(self.headers.get("Last-Modified")?).ok()
+    }
+
+    /// Evaluates the conditional headers of `req` against the `ETag` and
+    /// `Last-Modified` of this response, turning a successful response
+    /// into `304 Not Modified` or `412 Precondition Failed` when they say so.
+    pub fn check_preconditions(self, req: &HttpRequestHeader) -> Self {
+        if !(200..300).contains(&self.status_code) {
+            return self;
+        }
+        match evaluate_preconditions(req, self.headers.get("ETag"), self.last_modified()) {
+            Precondition::Proceed => self,
+            Precondition::NotModified => {
+                let mut resp = HttpResponse {
+                    version: self.version,
+                    ..Self::create_304_not_modified()
+                };
+                for (k, v) in self.headers {
+                    if NOT_MODIFIED_HEADERS.iter().any(|h| h.eq_ignore_ascii_case(&k)) {
+                        resp.headers.append(k, v);
                     }
+                }
+                resp
+            },
+            Precondition::Failed => HttpResponse {
+                version: self.version,
+                ..Self::create_412_precondition_failed()
+            },
+        }
+    }
+
+    /// Like [`Self::from_file`], but answers the `Range` and `If-Range`
+    /// headers of `req` with `206 Partial Content` or `416 Range Not Satisfiable`.
+    pub async fn serve_file(path: impl AsRef<Path>, req: &HttpRequestHeader) -> io::Result<Self> {
+        let path = path.as_ref();
+        let resp = Self::from_file(path).await?.check_preconditions(req);
+        if resp.status_code != 200 || !matches!(req.method, Method::GET | Method::HEAD) {
+            return Ok(resp);
+        }
+        let range = match req.get_para("Range") {
+            Some(range) => range,
+            None => return Ok(resp),
+        };
+        if let Some(if_range) = req.get_para("If-Range") {
+            if !resp.if_range_matches(if_range) {
+                return Ok(resp);
+            }
+        }
+        let len = resp.body.len().unwrap_or(0);
+        match parse_byte_ranges(range, len) {
+            ByteRanges::Ignore => Ok(resp),
+            ByteRanges::Unsatisfiable => {
+                let mut resp = HttpResponse {
+                    status_code: 416,
+                    status_msg: "Range Not Satisfiable".to_string(),
+                    body: ResponseBody::empty(),
+                    ..resp
+                };
+                resp.headers.remove("Content-Type");
+                resp.headers.insert("Content-Range".to_string(), format!("bytes */{}", len));
+                Ok(resp)
+            },
+            ByteRanges::Satisfiable(ranges) => {
+                let mut resp = HttpResponse {
+                    status_code: 206,
+                    status_msg: "Partial Content".to_string(),
+                    ..resp
+                };
+                if let [range] = &ranges[..] {
+                    resp.body = Self::file_range(path, range).await?;
+                    resp.headers.insert("Content-Range".to_string(), content_range(range, len));
                 } else {
-                    return Self::create_500_internal_server_error()
+                    let content_type = resp.headers.remove("Content-Type").unwrap_or_default();
+                    let boundary = format!("{:016x}", RandomState::new().hash_one(path));
+                    resp.body = Self::multipart_ranges(path, &ranges, len, &content_type, &boundary).await?;
+                    resp.headers.insert("Content-Type".to_string(), format!("multipart/byteranges; boundary={}", boundary));
                 }
+                Ok(resp)
+            },
+        }
+    }
+
+    /// RFC 9110 13.1.5: the range only applies if the validator still matches,
+    /// compared strongly against the `ETag` or exactly against the modification time.
+    fn if_range_matches(&self, if_range: &str) -> bool {
+        let if_range = if_range.trim();
+        if if_range.starts_with('"') || if_range.starts_with("W/") {
+            return match self.headers.get("ETag") {
+                Some(etag) => !etag.starts_with("W/") && etag == if_range,
+                None => false,
+            };
+        }
+        match (if_range.parse::<HttpDate>(), self.last_modified()) {
+            (Ok(date), Some(modified)) => date == HttpDate::from(modified),
+            _ => false,
+        }
+    }
+
+    async fn file_range(path: &Path, range: &Range<u64>) -> io::Result<ResponseBody> {
+        let mut file = File::open(path).await?;
+        file.seek(SeekFrom::Start(range.start)).await?;
+        Ok(ResponseBody::sized_reader(file.take(range.end - range.start), range.end - range.start))
+    }
+
+    /// Builds a `multipart/byteranges` body that streams each part from its own file handle.
+    async fn multipart_ranges(path: &Path, ranges: &[Range<u64>], len: u64, content_type: &str, boundary: &str) -> io::Result<ResponseBody> {
+        let mut body: Pin<Box<dyn AsyncRead + Send>> = Box::pin(tokio::io::empty());
+        let mut total = 0_u64;
+        for range in ranges {
+            let mut head = format!("\r\n--{}\r\n", boundary);
+            if !content_type.is_empty() {
+                head.push_str(&format!("Content-Type: {}\r\n", content_type));
             }
-        } else {
-            Self::create_500_internal_server_error()
+            head.push_str(&format!("Content-Range: {}\r\n\r\n", content_range(range, len)));
+            total += head.len() as u64 + (range.end - range.start);
+            let mut file = File::open(path).await?;
+            file.seek(SeekFrom::Start(range.start)).await?;
+            body = Box::pin(body.chain(Cursor::new(head)).chain(file.take(range.end - range.start)));
+        }
+        let tail = format!("\r\n--{}--\r\n", boundary);
+        total += tail.len() as u64;
+        body = Box::pin(body.chain(Cursor::new(tail)));
+        Ok(ResponseBody::Reader(body, Some(total)))
+    }
+
+    pub async fn from_html_file(path: impl AsRef<Path>) -> Self {
+        match Self::from_file(path).await {
+            Ok(mut resp) => {
+                resp.headers.insert("Content-Type".to_string(), "text/html; charset=utf-8".to_string());
+                resp
+            },
+            Err(_) => Self::create_500_internal_server_error(),
         }
     }
 
-    pub async fn write_to(&self, tx: &mut (impl AsyncWriteExt + Unpin)) -> Result<(), Error> {
+    /// Whether the body can only be delimited by closing the connection,
+    /// i.e. its length is unknown and the peer speaks HTTP/1.0.
+    pub fn is_close_delimited(&self) -> bool {
+        matches!(self.version, HttpVersion::HTTP1_0) && self.body.len().is_none() && self.status_allows_body()
+    }
 
+    // Takes `&mut self` as the body is `Send` but not `Sync`.
+    async fn write_head(&mut self, tx: &mut (impl AsyncWriteExt + Unpin)) -> Result<(), Error> {
         tx.write_all(self.version.to_str().as_bytes()).await?;
         tx.write_u8(b' ').await?;
         tx.write_all(self.status_code.to_string().as_bytes()).await?;
         tx.write_u8(b' ').await?;
         tx.write_all(self.status_msg.as_bytes()).await?;
         tx.write_all(b"\r\n").await?;
-        for (k, v) in &self.headers {
+        for (k, v) in self.headers.iter() {
             tx.write_all(k.as_bytes()).await?;
             tx.write_all(b": ").await?;
             tx.write_all(v.as_bytes()).await?;
             tx.write_all(b"\r\n").await?;
         }
-        tx.write_all(b"\r\n").await?;
-        tx.write_all(&self.body[..]).await?;
-        tx.write_all(b"\r\n").await?;
+        tx.write_all(b"\r\n").await
+    }
+
+    /// 1xx, 204 and 304 responses never carry a body (RFC 9112 6.3).
+    fn status_allows_body(&self) -> bool {
+        !(100..200).contains(&self.status_code) && self.status_code != 204 && self.status_code != 304
+    }
+
+    /// Sets the framing headers to match the body.
+    fn prepare_framing(&mut self) {
+        self.headers.remove("Transfer-Encoding");
+        if !self.status_allows_body() {
+            self.headers.remove("Content-Length");
+            self.body = ResponseBody::empty();
+            return;
+        }
+        match self.body.len() {
+            Some(len) => {
+                self.headers.insert("Content-Length".to_string(), len.to_string());
+            },
+            None => {
+                self.headers.remove("Content-Length");
+                match self.version {
+                    HttpVersion::HTTP1_0 => {
+                        self.headers.insert("Connection".to_string(), "close".to_string());
+                    },
+                    HttpVersion::HTTP1_1 => {
+                        self.headers.insert("Transfer-Encoding".to_string(), "chunked".to_string());
+                    },
+                }
+            },
+        }
+    }
+
+    /// Writes the status line and headers as `write_to` would, but no body:
+    /// the answer to a `HEAD` request. A handler answering `HEAD` with an
+    /// empty body keeps the `Content-Length` it set for the `GET` one.
+    pub async fn write_head_to(&mut self, tx: &mut (impl AsyncWriteExt + Unpin)) -> Result<(), Error> {
+        if self.status_allows_body() && self.body.is_empty() && self.headers.contains_key("Content-Length") {
+            self.headers.remove("Transfer-Encoding");
+        } else {
+            self.prepare_framing();
+        }
+        self.write_head(tx).await?;
+        tx.flush().await
+    }
+
+    pub async fn write_to(&mut self, tx: &mut (impl AsyncWriteExt + Unpin)) -> Result<(), Error> {
+
+        self.prepare_framing();
+        let chunked = self.headers.contains_key("Transfer-Encoding");
+        self.write_head(tx).await?;
+        match &mut self.body {
+            ResponseBody::Bytes(b) => {
+                tx.write_all(&b[..]).await?;
+            },
+            ResponseBody::Stream(s) => {
+                while let Some(chunk) = s.next().await {
+                    let chunk = chunk?;
+                    if chunked {
+                        write_chunk(tx, &chunk).await?;
+                    } else {
+                        tx.write_all(&chunk).await?;
+                    }
+                }
+            },
+            ResponseBody::Reader(r, Some(len)) => {
+                let copied = tokio::io::copy(&mut r.as_mut().take(*len), tx).await?;
+                if copied != *len {
+                    return Err(Error::new(io::ErrorKind::UnexpectedEof, "response body shorter than its length"));
+                }
+            },
+            ResponseBody::Reader(r, None) => {
+                let mut buf = vec![0_u8; CHUNK_SIZE];
+                loop {
+                    let n = r.read(&mut buf).await?;
+                    if n == 0 {
+                        break;
+                    }
+                    if chunked {
+                        write_chunk(tx, &buf[..n]).await?;
+                    } else {
+                        tx.write_all(&buf[..n]).await?;
+                    }
+                }
+            },
+        }
+        if chunked {
+            tx.write_all(b"0\r\n\r\n").await?;
+        }
         tx.flush().await?;
 
         Ok(())
     }
 
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn written(mut resp: HttpResponse) -> String {
+        let mut out = Vec::new();
+        resp.write_to(&mut out).await.unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    fn chunks() -> ResponseBody {
+        ResponseBody::stream(futures::stream::iter(vec![Ok(b"hello".to_vec()), Ok(Vec::new()), Ok(b", world".to_vec())]))
+    }
+
+    #[tokio::test]
+    async fn test_write_bytes() {
+        let out = written(HttpResponse { body: "hi".into(), ..HttpResponse::create_200_ok() }).await;
+        assert!(out.contains("Content-Length: 2\r\n"));
+        assert!(out.ends_with("\r\n\r\nhi"));
+    }
+
+    #[tokio::test]
+    async fn test_write_chunked_stream() {
+        let out = written(HttpResponse { body: chunks(), ..HttpResponse::create_200_ok() }).await;
+        assert!(out.contains("Transfer-Encoding: chunked\r\n"));
+        assert!(!out.contains("Content-Length"));
+        assert!(out.ends_with("\r\n\r\n5\r\nhello\r\n7\r\n, world\r\n0\r\n\r\n"));
+    }
+
+    #[tokio::test]
+    async fn test_write_close_delimited() {
+        let resp = HttpResponse { version: HttpVersion::HTTP1_0, body: ResponseBody::reader(&b"hello, world"[..]), ..HttpResponse::create_200_ok() };
+        assert!(resp.is_close_delimited());
+        let out = written(resp).await;
+        assert!(out.contains("Connection: close\r\n"));
+        assert!(!out.contains("Transfer-Encoding"));
+        assert!(out.ends_with("\r\n\r\nhello, world"));
+    }
+
+    #[tokio::test]
+    async fn test_not_modified_has_no_body() {
+        let mut resp = HttpResponse { body: "hello".into(), ..HttpResponse::create_200_ok() };
+        resp.set_etag("v1", false);
+        resp.headers.insert("Content-Type".to_string(), "text/plain".to_string());
+        let mut req = HttpRequestHeader::new();
+        req.paras.append("If-None-Match", "\"v1\"");
+        let resp = resp.check_preconditions(&req);
+        assert_eq!(resp.status_code, 304);
+        let out = written(resp).await;
+        assert!(out.contains("ETag: \"v1\"\r\n"));
+        assert!(!out.contains("Content-Length"));
+        assert!(!out.contains("Content-Type"));
+        assert!(out.ends_with("\r\n\r\n"));
+    }
+
+    #[tokio::test]
+    async fn test_write_sized_reader() {
+        let out = written(HttpResponse { body: ResponseBody::sized_reader(&b"hello, world"[..], 5), ..HttpResponse::create_200_ok() }).await;
+        assert!(out.contains("Content-Length: 5\r\n"));
+        assert!(out.ends_with("\r\n\r\nhello"));
+    }
+
+    /// A 20 byte file under a name of its own, and the response serving it for `paras`.
+    async fn served(name: &str, paras: &[(&str, &str)]) -> (std::path::PathBuf, HttpResponse) {
+        let path = std::env::temp_dir().join(format!("serve_file_test_{}_{}.txt", std::process::id(), name));
+        std::fs::write(&path, "0123456789abcdefghij").unwrap();
+        let mut req = HttpRequestHeader::new();
+        for (k, v) in paras {
+            req.paras.append(*k, *v);
+        }
+        let resp = HttpResponse::serve_file(&path, &req).await.unwrap();
+        (path, resp)
+    }
+
+    #[tokio::test]
+    async fn test_serve_file_range() {
+        let (path, resp) = served("single", &[("Range", "bytes=2-5")]).await;
+        assert_eq!(resp.status_code, 206);
+        let out = written(resp).await;
+        assert!(out.contains("Content-Range: bytes 2-5/20\r\n"), "{}", out);
+        assert!(out.contains("Content-Length: 4\r\n"), "{}", out);
+        assert!(out.ends_with("\r\n\r\n2345"), "{}", out);
+        std::fs::remove_file(path).unwrap();
+
+        let (path, resp) = served("unsatisfiable", &[("Range", "bytes=30-")]).await;
+        assert_eq!(resp.status_code, 416);
+        let out = written(resp).await;
+        assert!(out.contains("Content-Range: bytes */20\r\n"), "{}", out);
+        assert!(!out.contains("Content-Type"), "{}", out);
+        assert!(out.ends_with("\r\n\r\n"), "{}", out);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_serve_file_multipart() {
+        let (path, resp) = served("multipart", &[("Range", "bytes=0-1,-3")]).await;
+        assert_eq!(resp.status_code, 206);
+        let content_type = resp.headers.get("Content-Type").unwrap().to_string();
+        let boundary = content_type.strip_prefix("multipart/byteranges; boundary=").unwrap();
+        let body = format!(concat!(
+            "\r\n--{b}\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Range: bytes 0-1/20\r\n\r\n01",
+            "\r\n--{b}\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Range: bytes 17-19/20\r\n\r\nhij",
+            "\r\n--{b}--\r\n"), b = boundary);
+        assert_eq!(resp.body.len(), Some(body.len() as u64));
+        let out = written(resp).await;
+        assert!(out.contains(&format!("Content-Length: {}\r\n", body.len())), "{}", out);
+        assert!(out.ends_with(&format!("\r\n\r\n{}", body)), "{}", out);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_serve_file_if_range() {
+        let (path, full) = served("if_range", &[]).await;
+        let etag = full.headers.get("ETag").unwrap().to_string();
+        let modified = full.headers.get("Last-Modified").unwrap().to_string();
+        let status = |if_range: String| {
+            let path = &path;
+            async move {
+                let mut req = HttpRequestHeader::new();
+                req.paras.append("Range", "bytes=0-3");
+                req.paras.append("If-Range", if_range);
+                HttpResponse::serve_file(path, &req).await.unwrap().status_code
+            }
+        };
+        assert_eq!(status(etag.clone()).await, 206);
+        assert_eq!(status(format!("W/{}", etag)).await, 200);
+        assert_eq!(status("\"other\"".to_string()).await, 200);
+        assert_eq!(status(modified).await, 206);
+        assert_eq!(status(httpdate::fmt_http_date(UNIX_EPOCH)).await, 200);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_write_head() {
+        let mut resp = HttpResponse::create_200_ok();
+        resp.headers.insert("Content-Length".to_string(), "42".to_string());
+        let mut out = Vec::new();
+        resp.write_head_to(&mut out).await.unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("Content-Length: 42\r\n"));
+        assert!(out.ends_with("\r\n\r\n"));
+
+        let mut resp = HttpResponse { body: "hello".into(), ..HttpResponse::create_200_ok() };
+        let mut out = Vec::new();
+        resp.write_head_to(&mut out).await.unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("Content-Length: 5\r\n"));
+        assert!(out.ends_with("\r\n\r\n"));
+    }
+
+}
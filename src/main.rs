@@ -1,16 +1,19 @@
-mod app;
-mod http;
-
 use std::{future::Future, pin::Pin, sync::Arc};
-use http::{HttpRequest, HttpResponse};
-use app::{HandleError, HttpResult, Application};
+use webserver::{ep_wrap, mw_wrap, http::{HttpRequest, HttpResponse}, app::{HttpResult, Application, Next, StaticDir}};
+
+async fn log<'a, 'b>(req: &'a mut HttpRequest<'b>, captures: Vec<&'b str>, next: Next<'a>) -> HttpResult {
+    let header = req.header;
+    let resp = next.run(req, captures).await?;
+    println!("{} {} -> {}", header.method, header.url, resp.status_code);
+    Ok(resp)
+}
 
-async fn hello(req: &mut HttpRequest<'_>, captures: Vec<&'_ str>) -> HttpResult {
-    println!("{:?}", req.header.paras.get("Connection"));
+async fn hello(req: &mut HttpRequest<'_>, _captures: Vec<&'_ str>) -> HttpResult {
+    println!("{:?}", req.header.paras.connection().collect::<Vec<_>>());
     Ok(HttpResponse::from_html_file("./asset/hello.html").await)
 }
 
-async fn notfound(req: &mut HttpRequest<'_>, captures: Vec<&'_ str>) -> HttpResult {
+async fn notfound(_req: &mut HttpRequest<'_>, _captures: Vec<&'_ str>) -> HttpResult {
     // println!("{:?}", &req);
     Ok(HttpResponse{status_code:404, status_msg: "Not Found".to_string(), ..HttpResponse::from_html_file("./asset/notfound.html").await})
 }
@@ -21,11 +24,18 @@ async fn main()  {
     let app = Application::new();
     app.listen_tcp("127.0.0.1:8000").await.unwrap()
         .listen_tcp("127.0.0.1:8080").await.unwrap()
+        .middleware(mw_wrap!(log))
+        .serve_route_table("/_routes")
         .registrar()
         .get()
         .at("/hello")
         .register("", ep_wrap!(hello))
         .register("/{}", ep_wrap!(hello))
         .app()
+        .serve_dir("/static", StaticDir::new("./asset").unwrap())
+        .registrar()
+        .get()
+        .register("/{path}", ep_wrap!(notfound))
+        .app()
         .run().await;
 }